@@ -0,0 +1,544 @@
+// SPDX-License-Identifier: MIT
+
+use crate::http;
+use crate::settings;
+use crate::CategoryDirectory;
+
+use log::{debug, warn};
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::Read;
+use std::path::PathBuf;
+
+/// An opened file and the validators of its download.
+pub type Opened = (Box<dyn Read>, http::Validators);
+
+/// A source to scan the primary mirror from.
+///
+/// Each category `type` in the configuration file is implemented
+/// as a `ScanBackend`. A backend lists all entries of the category
+/// into the `CategoryDirectory` map and retrieves single files
+/// which are needed for checksum creation (`repomd.xml`, checksum files).
+pub trait ScanBackend: Sync {
+    /// The category type this backend implements
+    fn name(&self) -> &str;
+
+    /// Scan the primary mirror and add all found entries to `cds`.
+    fn scan(
+        &self,
+        cds: &mut HashMap<String, CategoryDirectory>,
+        excludes: &[String],
+        topdir: &str,
+    ) -> Result<(), Box<dyn Error>>;
+
+    /// Open the file `target` in `dir` for checksum creation.
+    ///
+    /// The returned reader streams the raw content of the file.
+    fn open_file(
+        &self,
+        topdir: &str,
+        dir: &str,
+        target: &str,
+    ) -> Result<Box<dyn Read>, Box<dyn Error>>;
+
+    /// The URL the file `target` in `dir` is downloaded from.
+    ///
+    /// Returns `None` if the backend does not download files.
+    fn url(&self, _topdir: &str, _dir: &str, _target: &str) -> Option<String> {
+        None
+    }
+
+    /// Open the file `target` in `dir` unless it is unchanged since
+    /// it was opened with the result `validators`.
+    ///
+    /// Returns `None` if the file is unchanged. Otherwise the reader
+    /// and the validators for the next call. The default implementation
+    /// always opens the file and returns empty validators.
+    fn open_file_if_modified(
+        &self,
+        topdir: &str,
+        dir: &str,
+        target: &str,
+        _validators: Option<&http::Validators>,
+    ) -> Result<Option<Opened>, Box<dyn Error>> {
+        Ok(Some((
+            self.open_file(topdir, dir, target)?,
+            http::Validators::default(),
+        )))
+    }
+
+    /// The path of the file `target` in `dir` on the local file system.
+    ///
+    /// Returns `None` if the backend has no local access to the files.
+    fn local_path(&self, _topdir: &str, _dir: &str, _target: &str) -> Option<PathBuf> {
+        None
+    }
+
+    /// The directory of the category on the local file system.
+    ///
+    /// Returns `None` if the backend does not scan the local file system.
+    fn local_directory(&self) -> Option<&str> {
+        None
+    }
+}
+
+/// Scan the primary mirror using `rsync` and download files
+/// for checksum creation via HTTP(S) from `checksum_base`.
+pub struct Rsync {
+    /// rsync URL of the category
+    pub url: String,
+    /// rsync options from the category
+    pub category_rsync_options: Vec<String>,
+    /// rsync options for all categories
+    pub rsync_options: Vec<String>,
+    /// HTTP(S) URL used to download files for checksum creation
+    pub checksum_base: Option<String>,
+    /// Client used to download from `checksum_base`
+    pub client: http::Client,
+    /// Do not use a remote `fullfiletimelist-*` but do a full listing
+    pub skip_fftl: bool,
+    /// Name of the category
+    pub category_name: String,
+    /// Name of the remote `fullfiletimelist-*`
+    pub fullfiletimelist: Option<String>,
+    /// A remote `fullfiletimelist-*` older than this is not used
+    pub max_fullfiletimelist_hours: i64,
+}
+
+/// Scan the primary mirror by crawling HTTP(S) directory indexes
+/// and download files for checksum creation from `checksum_base`.
+pub struct Http {
+    /// HTTP(S) URL of the category
+    pub url: String,
+    /// HTTP(S) URL used to download files for checksum creation
+    pub checksum_base: Option<String>,
+    /// Client used to crawl `url` and to download from `checksum_base`
+    pub client: http::Client,
+    /// Do not use a remote `fullfiletimelist-*` but crawl all indexes
+    pub skip_fftl: bool,
+    /// Name of the category
+    pub category_name: String,
+    /// Name of the remote `fullfiletimelist-*`
+    pub fullfiletimelist: Option<String>,
+    /// A remote `fullfiletimelist-*` older than this is not used
+    pub max_fullfiletimelist_hours: i64,
+    /// Crawled directories containing one of these checksum
+    /// files need the exact size of all files
+    pub checksum_files: Vec<glob::Pattern>,
+}
+
+/// Scan the primary mirror on the local file system.
+pub struct Directory {
+    /// Path of the category on the local file system
+    pub url: String,
+    /// Do not use a `fullfiletimelist-*` but walk the file system
+    pub skip_fftl: bool,
+    /// Name of the category
+    pub category_name: String,
+    /// The path on the local file system which contains
+    /// the topdir of the category
+    pub local_prefix: Option<String>,
+}
+
+fn full_target(
+    base: &Option<String>,
+    backend: &str,
+    config_name: &str,
+    topdir: &str,
+    dir: &str,
+    target: &str,
+) -> Result<String, Box<dyn Error>> {
+    let base = match base {
+        Some(b) => b,
+        _ => {
+            return Err(format!(
+                "For backend '{}' '{}' needs to be set",
+                backend, config_name
+            )
+            .into())
+        }
+    };
+
+    Ok(format!("{}{}{}/{}", base, topdir, dir, target))
+}
+
+/// Add the entries of a downloaded `fullfiletimelist-*` to `cds`.
+///
+/// Returns `false` if no `fullfiletimelist-*` could be used and
+/// a full listing of the category is necessary. Fails if the name
+/// of the `fullfiletimelist-*` is ambiguous.
+fn scan_fetched_fullfiletimelist(
+    fetched: Result<Option<Vec<u8>>, Box<dyn Error>>,
+    cds: &mut HashMap<String, CategoryDirectory>,
+    excludes: &[String],
+    topdir: &str,
+    category_name: &str,
+) -> Result<bool, Box<dyn Error>> {
+    let data = match fetched {
+        Ok(Some(d)) => d,
+        Ok(None) => return Ok(false),
+        Err(e) if e.is::<crate::AmbiguousFullfiletimelist>() => return Err(e),
+        Err(e) => {
+            warn!("Retrieving fullfiletimelist failed: {}", e);
+            return Ok(false);
+        }
+    };
+
+    // Only use the result if the complete file could be parsed
+    let mut fftl_cds: HashMap<String, CategoryDirectory> = HashMap::new();
+    if let Err(e) =
+        crate::parse_fullfiletimelist(&data, &mut fftl_cds, excludes, topdir, category_name)
+    {
+        warn!("Parsing fullfiletimelist failed: {}", e);
+        return Ok(false);
+    }
+    cds.extend(fftl_cds);
+
+    Ok(true)
+}
+
+/// Start downloading `url` and return the response to read the body from.
+fn download(client: &http::Client, url: &str) -> Result<Box<dyn Read>, Box<dyn Error>> {
+    let resp = client.get(url)?;
+
+    {
+        use reqwest::StatusCode;
+
+        if resp.status() != StatusCode::OK {
+            return Err(
+                format!("Downloading '{}' returned status '{}'", url, resp.status()).into(),
+            );
+        };
+    }
+
+    Ok(Box::new(resp))
+}
+
+/// Start downloading `url` unless it is unchanged since the response
+/// `validators` were taken from.
+///
+/// Returns `None` if the server reports the file as unchanged.
+fn download_if_modified(
+    client: &http::Client,
+    url: &str,
+    validators: Option<&http::Validators>,
+) -> Result<Option<Opened>, Box<dyn Error>> {
+    let resp = match validators {
+        Some(v) => client.get_if_modified(url, v)?,
+        None => client.get(url)?,
+    };
+
+    match resp.status() {
+        reqwest::StatusCode::OK => {
+            let validators = http::Validators::from_response(&resp);
+            Ok(Some((Box::new(resp), validators)))
+        }
+        reqwest::StatusCode::NOT_MODIFIED if validators.is_some() => Ok(None),
+        status => Err(format!("Downloading '{}' returned status '{}'", url, status).into()),
+    }
+}
+
+/// Downloads files for checksum creation from `checksum_base`.
+///
+/// Shared by the backends which cannot read the files locally.
+struct ChecksumBase<'a> {
+    backend: &'a str,
+    base: &'a Option<String>,
+    client: &'a http::Client,
+}
+
+impl ChecksumBase<'_> {
+    fn url(&self, topdir: &str, dir: &str, target: &str) -> Result<String, Box<dyn Error>> {
+        full_target(
+            self.base,
+            self.backend,
+            "checksum_base",
+            topdir,
+            dir,
+            target,
+        )
+    }
+
+    fn open_file(
+        &self,
+        topdir: &str,
+        dir: &str,
+        target: &str,
+    ) -> Result<Box<dyn Read>, Box<dyn Error>> {
+        let url = self.url(topdir, dir, target)?;
+        debug!("Reading {} for checksum creation", url);
+
+        download(self.client, &url)
+    }
+
+    fn open_file_if_modified(
+        &self,
+        topdir: &str,
+        dir: &str,
+        target: &str,
+        validators: Option<&http::Validators>,
+    ) -> Result<Option<Opened>, Box<dyn Error>> {
+        let url = self.url(topdir, dir, target)?;
+        debug!("Reading {} for checksum creation", url);
+
+        download_if_modified(self.client, &url, validators)
+    }
+}
+
+impl Rsync {
+    fn checksum_base(&self) -> ChecksumBase<'_> {
+        ChecksumBase {
+            backend: self.name(),
+            base: &self.checksum_base,
+            client: &self.client,
+        }
+    }
+}
+
+impl Http {
+    fn checksum_base(&self) -> ChecksumBase<'_> {
+        ChecksumBase {
+            backend: self.name(),
+            base: &self.checksum_base,
+            client: &self.client,
+        }
+    }
+}
+
+impl ScanBackend for Rsync {
+    fn name(&self) -> &str {
+        "rsync"
+    }
+
+    fn scan(
+        &self,
+        cds: &mut HashMap<String, CategoryDirectory>,
+        excludes: &[String],
+        topdir: &str,
+    ) -> Result<(), Box<dyn Error>> {
+        if !self.skip_fftl {
+            let fetched = crate::fetch_fullfiletimelist_with_rsync(
+                &crate::fullfiletimelist_base(&self.url, &self.category_name),
+                &self.fullfiletimelist,
+                &self.category_rsync_options,
+                &self.rsync_options,
+                self.max_fullfiletimelist_hours,
+            );
+            if scan_fetched_fullfiletimelist(fetched, cds, excludes, topdir, &self.category_name)? {
+                return Ok(());
+            }
+        }
+
+        crate::scan_with_rsync(
+            cds,
+            excludes,
+            topdir,
+            &self.category_rsync_options,
+            &self.rsync_options,
+            &self.url,
+        )
+    }
+
+    fn open_file(
+        &self,
+        topdir: &str,
+        dir: &str,
+        target: &str,
+    ) -> Result<Box<dyn Read>, Box<dyn Error>> {
+        self.checksum_base().open_file(topdir, dir, target)
+    }
+
+    fn url(&self, topdir: &str, dir: &str, target: &str) -> Option<String> {
+        self.checksum_base().url(topdir, dir, target).ok()
+    }
+
+    fn open_file_if_modified(
+        &self,
+        topdir: &str,
+        dir: &str,
+        target: &str,
+        validators: Option<&http::Validators>,
+    ) -> Result<Option<Opened>, Box<dyn Error>> {
+        self.checksum_base()
+            .open_file_if_modified(topdir, dir, target, validators)
+    }
+}
+
+impl ScanBackend for Http {
+    fn name(&self) -> &str {
+        "http"
+    }
+
+    fn scan(
+        &self,
+        cds: &mut HashMap<String, CategoryDirectory>,
+        excludes: &[String],
+        topdir: &str,
+    ) -> Result<(), Box<dyn Error>> {
+        if !self.skip_fftl {
+            let fetched = crate::fetch_fullfiletimelist_with_http(
+                &crate::fullfiletimelist_base(&self.url, &self.category_name),
+                &self.fullfiletimelist,
+                self.max_fullfiletimelist_hours,
+                &self.client,
+            );
+            if scan_fetched_fullfiletimelist(fetched, cds, excludes, topdir, &self.category_name)? {
+                return Ok(());
+            }
+        }
+
+        crate::scan_with_http(
+            cds,
+            excludes,
+            topdir,
+            &self.url,
+            &self.client,
+            &self.checksum_files,
+        )
+    }
+
+    fn open_file(
+        &self,
+        topdir: &str,
+        dir: &str,
+        target: &str,
+    ) -> Result<Box<dyn Read>, Box<dyn Error>> {
+        self.checksum_base().open_file(topdir, dir, target)
+    }
+
+    fn url(&self, topdir: &str, dir: &str, target: &str) -> Option<String> {
+        self.checksum_base().url(topdir, dir, target).ok()
+    }
+
+    fn open_file_if_modified(
+        &self,
+        topdir: &str,
+        dir: &str,
+        target: &str,
+        validators: Option<&http::Validators>,
+    ) -> Result<Option<Opened>, Box<dyn Error>> {
+        self.checksum_base()
+            .open_file_if_modified(topdir, dir, target, validators)
+    }
+}
+
+impl ScanBackend for Directory {
+    fn name(&self) -> &str {
+        "directory"
+    }
+
+    fn scan(
+        &self,
+        cds: &mut HashMap<String, CategoryDirectory>,
+        excludes: &[String],
+        topdir: &str,
+    ) -> Result<(), Box<dyn Error>> {
+        crate::scan_local_directory(
+            cds,
+            excludes,
+            topdir,
+            &self.url,
+            self.skip_fftl,
+            &self.category_name,
+        )
+    }
+
+    fn open_file(
+        &self,
+        topdir: &str,
+        dir: &str,
+        target: &str,
+    ) -> Result<Box<dyn Read>, Box<dyn Error>> {
+        let full_target = full_target(
+            &self.local_prefix,
+            self.name(),
+            "local_prefix",
+            topdir,
+            dir,
+            target,
+        )?;
+
+        debug!("Reading {} for checksum creation", full_target);
+
+        Ok(Box::new(std::fs::File::open(full_target)?))
+    }
+
+    fn local_path(&self, topdir: &str, dir: &str, target: &str) -> Option<PathBuf> {
+        full_target(
+            &self.local_prefix,
+            self.name(),
+            "local_prefix",
+            topdir,
+            dir,
+            target,
+        )
+        .ok()
+        .map(PathBuf::from)
+    }
+
+    fn local_directory(&self) -> Option<&str> {
+        Some(&self.url)
+    }
+}
+
+/// Create the backend for the category `type` from the configuration file.
+///
+/// * `rsync_options` - the rsync options for all categories
+/// * `max_fullfiletimelist_hours` - maximum age of a remote `fullfiletimelist-*`
+/// * `topdir` - the topdir of the category as found in the database
+/// * `skip_fftl` - do not use a `fullfiletimelist-*`
+/// * `http` - the HTTP(S) client settings of the category
+pub fn new(
+    category: &settings::Category,
+    rsync_options: &[String],
+    max_fullfiletimelist_hours: i64,
+    topdir: &str,
+    skip_fftl: bool,
+    http: &settings::Http,
+    checksum_files: &[glob::Pattern],
+) -> Result<Box<dyn ScanBackend>, Box<dyn Error>> {
+    match category.r#type.as_str() {
+        "rsync" => Ok(Box::new(Rsync {
+            url: category.url.clone(),
+            category_rsync_options: match &category.options {
+                Some(opt) => opt.split(' ').map(str::to_string).collect::<Vec<String>>(),
+                _ => vec![],
+            },
+            rsync_options: rsync_options.to_vec(),
+            checksum_base: category.checksum_base.clone(),
+            client: http::Client::new(http)?,
+            skip_fftl,
+            category_name: category.name.clone(),
+            fullfiletimelist: category.fullfiletimelist.clone(),
+            max_fullfiletimelist_hours,
+        })),
+        "http" => Ok(Box::new(Http {
+            url: category.url.clone(),
+            checksum_base: category.checksum_base.clone(),
+            client: http::Client::new(http)?,
+            skip_fftl,
+            category_name: category.name.clone(),
+            fullfiletimelist: category.fullfiletimelist.clone(),
+            max_fullfiletimelist_hours,
+            checksum_files: checksum_files.to_vec(),
+        })),
+        "directory" => Ok(Box::new(Directory {
+            url: category.url.clone(),
+            skip_fftl,
+            category_name: category.name.clone(),
+            local_prefix: Some(
+                category
+                    .url
+                    .split(topdir)
+                    .map(str::to_string)
+                    .collect::<Vec<String>>()[0]
+                    .clone(),
+            ),
+        })),
+        _ => Err(format!(
+            "Cannot handle type '{}' of category '{}'",
+            category.r#type, category.name
+        )
+        .into()),
+    }
+}
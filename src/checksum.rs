@@ -0,0 +1,187 @@
+// SPDX-License-Identifier: MIT
+
+use glob::Pattern;
+use std::error::Error;
+
+/// Files matching one of these patterns are parsed for checksums
+/// if no `checksum_files` are configured.
+pub const DEFAULT_PATTERNS: &[&str] = &["*-CHECKSUM"];
+
+/// The hash algorithms supported in checksum files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Algorithm {
+    Md5,
+    Sha1,
+    Sha256,
+    Sha512,
+}
+
+impl Algorithm {
+    /// The algorithm named in a BSD style line (`SHA256 (name) = hash`).
+    fn from_tag(tag: &str) -> Option<Algorithm> {
+        match tag {
+            "MD5" => Some(Algorithm::Md5),
+            "SHA1" => Some(Algorithm::Sha1),
+            "SHA256" => Some(Algorithm::Sha256),
+            "SHA512" => Some(Algorithm::Sha512),
+            _ => None,
+        }
+    }
+
+    /// GNU style lines (`hash  name`) do not name the algorithm.
+    /// It is derived from the length of the hex encoded hash.
+    fn from_length(length: usize) -> Option<Algorithm> {
+        match length {
+            32 => Some(Algorithm::Md5),
+            40 => Some(Algorithm::Sha1),
+            64 => Some(Algorithm::Sha256),
+            128 => Some(Algorithm::Sha512),
+            _ => None,
+        }
+    }
+
+    fn hex_length(self) -> usize {
+        match self {
+            Algorithm::Md5 => 32,
+            Algorithm::Sha1 => 40,
+            Algorithm::Sha256 => 64,
+            Algorithm::Sha512 => 128,
+        }
+    }
+}
+
+/// One checksum from a checksum file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub algorithm: Algorithm,
+    /// The file name with all escapes resolved
+    pub name: String,
+    /// The lower case hex encoded hash
+    pub hash: String,
+}
+
+/// Resolve the escapes GNU coreutils uses for file names
+/// containing a backslash or a line break.
+fn unescape(name: &str) -> Option<String> {
+    let mut result = String::with_capacity(name.len());
+    let mut chars = name.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        match chars.next()? {
+            '\\' => result.push('\\'),
+            'n' => result.push('\n'),
+            'r' => result.push('\r'),
+            _ => return None,
+        }
+    }
+
+    Some(result)
+}
+
+fn is_hash(hash: &str, algorithm: Algorithm) -> bool {
+    hash.len() == algorithm.hex_length() && hash.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Parse a BSD style line: `SHA256 (name) = hash`
+fn parse_bsd(line: &str, escaped: bool) -> Option<Entry> {
+    let (tag, rest) = line.split_once(" (")?;
+    let algorithm = Algorithm::from_tag(tag)?;
+    let (name, hash) = rest.rsplit_once(") = ")?;
+    if !is_hash(hash, algorithm) || name.is_empty() {
+        return None;
+    }
+
+    Some(Entry {
+        algorithm,
+        name: match escaped {
+            true => unescape(name)?,
+            false => name.to_string(),
+        },
+        hash: hash.to_ascii_lowercase(),
+    })
+}
+
+/// Parse a GNU style line: `hash  name` or `hash *name` (binary mode).
+/// The single space separated output of BSD `sha256 -r` is also accepted.
+fn parse_gnu(line: &str, escaped: bool) -> Option<Entry> {
+    let (hash, rest) = line.split_once(' ')?;
+    let algorithm = Algorithm::from_length(hash.len())?;
+    if !is_hash(hash, algorithm) {
+        return None;
+    }
+    let name = rest
+        .strip_prefix(' ')
+        .or_else(|| rest.strip_prefix('*'))
+        .unwrap_or(rest);
+    if name.is_empty() {
+        return None;
+    }
+
+    Some(Entry {
+        algorithm,
+        name: match escaped {
+            true => unescape(name)?,
+            false => name.to_string(),
+        },
+        hash: hash.to_ascii_lowercase(),
+    })
+}
+
+/// Parse one line of a checksum file.
+///
+/// Returns `None` for comments, empty lines and lines
+/// in an unknown format.
+pub fn parse_line(line: &str) -> Option<Entry> {
+    let line = line.trim_end_matches('\r');
+    if line.starts_with('#') {
+        return None;
+    }
+    // A leading backslash marks a line with an escaped file name
+    let (line, escaped) = match line.strip_prefix('\\') {
+        Some(l) => (l, true),
+        None => (line, false),
+    };
+
+    parse_bsd(line, escaped).or_else(|| parse_gnu(line, escaped))
+}
+
+/// Parse all checksums of a file in BSD or GNU coreutils format.
+///
+/// Both formats can be mixed in one file. Lines which are not
+/// checksums (comments, OpenPGP armor, ...) are skipped.
+pub fn parse(content: &str) -> Vec<Entry> {
+    content.lines().filter_map(parse_line).collect()
+}
+
+/// Compile the file name patterns of checksum files.
+///
+/// Uses `DEFAULT_PATTERNS` if `patterns` is not set.
+pub fn patterns(patterns: Option<&[String]>) -> Result<Vec<Pattern>, Box<dyn Error>> {
+    let mut result = Vec::new();
+    match patterns {
+        Some(p) => {
+            for p in p {
+                result.push(
+                    Pattern::new(p)
+                        .map_err(|e| format!("Invalid checksum file pattern {}: {}", p, e))?,
+                );
+            }
+        }
+        None => {
+            for p in DEFAULT_PATTERNS {
+                result.push(Pattern::new(p)?);
+            }
+        }
+    }
+
+    Ok(result)
+}
+
+/// Check if the file `name` is a checksum file.
+pub fn is_checksum_file(name: &str, patterns: &[Pattern]) -> bool {
+    patterns.iter().any(|p| p.matches(name))
+}
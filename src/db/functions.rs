@@ -1,10 +1,30 @@
 // SPDX-License-Identifier: MIT
 
-use crate::db::models::{Arch, CategoryDirectory, Directory, FileDetail, Repository, Version};
-use crate::debug::*;
+use crate::db::models::{
+    Arch, CategoryDirectory, Directory, FileDetail, InsertFileDetailDigest, Repository, Version,
+};
+use crate::dry_run;
 
 use diesel::pg::PgConnection;
 use diesel::prelude::*;
+use log::{debug, info};
+
+/// First key of all advisory locks taken for a product
+pub const PRODUCT_LOCK: i32 = 1;
+/// First key of the advisory lock held while a category is scanned
+pub const CATEGORY_LOCK: i32 = 2;
+
+#[derive(QueryableByName)]
+struct AdvisoryLock {
+    #[diesel(sql_type = diesel::sql_types::Bool)]
+    locked: bool,
+}
+
+#[derive(QueryableByName)]
+struct TableExists {
+    #[diesel(sql_type = diesel::sql_types::Bool)]
+    exists: bool,
+}
 
 #[derive(Debug, Default, Queryable, Clone)]
 pub struct Category {
@@ -21,7 +41,7 @@ pub fn get_arches(c: &mut PgConnection) -> Result<Vec<Arch>, diesel::result::Err
     use crate::db::schema::arch::dsl::*;
     let query = arch.select((id, name));
     let debug = diesel::debug_query::<diesel::pg::Pg, _>(&query);
-    print_step(debug.to_string());
+    debug!("{}", debug);
     query.load::<Arch>(c)
 }
 
@@ -32,10 +52,79 @@ pub fn get_versions(c: &mut PgConnection) -> Result<Vec<Version>, diesel::result
     use crate::db::schema::version::dsl::*;
     let query = version.select((id, name, product_id, is_test));
     let debug = diesel::debug_query::<diesel::pg::Pg, _>(&query);
-    print_step(debug.to_string());
+    debug!("{}", debug);
     query.load::<Version>(c)
 }
 
+/// Serialize the creation of versions and repositories of a product.
+///
+/// Categories of the same product can be scanned concurrently. Before
+/// creating a version or a repository this lock has to be taken and it
+/// has to be checked again if the entry has been created by another scan
+/// in the meantime. The lock is released at the end of the transaction.
+pub fn lock_product(c: &mut PgConnection, product_id: i32) -> Result<(), diesel::result::Error> {
+    use diesel::sql_types::Integer;
+
+    let query = diesel::sql_query("SELECT pg_advisory_xact_lock($1, $2)")
+        .bind::<Integer, _>(PRODUCT_LOCK)
+        .bind::<Integer, _>(product_id);
+    let debug = diesel::debug_query::<diesel::pg::Pg, _>(&query);
+    debug!("{}", debug);
+    query.execute(c)?;
+
+    Ok(())
+}
+
+/// Try to take the lock of a category for this session.
+///
+/// Returns `false` if another scan of the category holds the lock.
+/// The lock is held until the connection is closed.
+pub fn try_lock_category(
+    c: &mut PgConnection,
+    category_id: i32,
+) -> Result<bool, diesel::result::Error> {
+    use diesel::sql_types::Integer;
+
+    let query = diesel::sql_query("SELECT pg_try_advisory_lock($1, $2) AS locked")
+        .bind::<Integer, _>(CATEGORY_LOCK)
+        .bind::<Integer, _>(category_id);
+    let debug = diesel::debug_query::<diesel::pg::Pg, _>(&query);
+    debug!("{}", debug);
+    Ok(query.get_result::<AdvisoryLock>(c)?.locked)
+}
+
+/// Retrieve a single version of a product from the database
+pub fn get_version(
+    c: &mut PgConnection,
+    version_product_id: i32,
+    version_name: &str,
+) -> Result<Option<Version>, diesel::result::Error> {
+    use crate::db::schema::version::dsl::*;
+    let query = version
+        .select((id, name, product_id, is_test))
+        .filter(product_id.eq(version_product_id))
+        .filter(name.eq(version_name));
+    let debug = diesel::debug_query::<diesel::pg::Pg, _>(&query);
+    debug!("{}", debug);
+    query.first::<Version>(c).optional()
+}
+
+/// Check if a repository with the given prefix and architecture exists
+pub fn repository_exists(
+    c: &mut PgConnection,
+    repository_prefix: &str,
+    repository_arch_id: i32,
+) -> Result<bool, diesel::result::Error> {
+    use crate::db::schema::repository::dsl::*;
+    let query = repository
+        .select(id)
+        .filter(prefix.eq(repository_prefix))
+        .filter(arch_id.eq(repository_arch_id));
+    let debug = diesel::debug_query::<diesel::pg::Pg, _>(&query);
+    debug!("{}", debug);
+    Ok(query.first::<i32>(c).optional()?.is_some())
+}
+
 /// Creates a repository in the database using the given parameters.
 pub fn create_repository(
     c: &mut PgConnection,
@@ -58,17 +147,162 @@ pub fn create_repository(
         repository::dsl::disabled.eq(false),
     ));
 
-    STEPS.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
-
     let debug = diesel::debug_query::<diesel::pg::Pg, _>(&insert);
-    print_step(debug.to_string());
-    println!(
+    debug!("{}", debug);
+    info!(
         "Created Repository(prefix={}, version={}, arch={}, category={}) -> Directory {}",
         prefix, version_id, arch_id, cat_id, directory_id
     );
+    if dry_run::record("INSERT", "repository", debug.to_string()) {
+        return Ok(1);
+    }
+    insert.execute(c)
+}
+
+/// Disable the repositories of a directory and detach them from it.
+///
+/// The repositories keep their prefix and architecture so that
+/// `attach_repository()` can enable them again.
+pub fn detach_repositories(
+    c: &mut PgConnection,
+    old_directory_id: i32,
+) -> Result<usize, diesel::result::Error> {
+    use crate::db::schema::repository::dsl::*;
+
+    let update = diesel::update(repository.filter(directory_id.eq(old_directory_id)))
+        .set((disabled.eq(true), directory_id.eq(None::<i32>)));
+
+    let debug = diesel::debug_query::<diesel::pg::Pg, _>(&update);
+    debug!("{}", debug);
+    if dry_run::record("UPDATE", "repository", debug.to_string()) {
+        return Ok(0);
+    }
+    update.execute(c)
+}
+
+/// Enable the detached repository `repository_id` and attach it
+/// to the directory `with_topdir` of another location.
+///
+/// Returns 0 if the repository is no longer detached.
+pub fn attach_repository(
+    c: &mut PgConnection,
+    repository_id: i32,
+    new_directory_id: i32,
+    with_topdir: String,
+    cat_id: i32,
+    new_version_id: i32,
+) -> Result<usize, diesel::result::Error> {
+    use crate::db::schema::repository::dsl::*;
+
+    let update = diesel::update(
+        repository
+            .filter(id.eq(repository_id))
+            .filter(directory_id.is_null()),
+    )
+    .set((
+        disabled.eq(false),
+        directory_id.eq(new_directory_id),
+        name.eq(&with_topdir),
+        category_id.eq(cat_id),
+        version_id.eq(new_version_id),
+    ));
+
+    let debug = diesel::debug_query::<diesel::pg::Pg, _>(&update);
+    debug!("{}", debug);
+    if dry_run::record("UPDATE", "repository", debug.to_string()) {
+        return Ok(1);
+    }
+    update.execute(c)
+}
+
+/// Reset the ctime of a directory.
+///
+/// The next scan will see the directory as changed and look at it again.
+pub fn reset_directory_ctime(
+    c: &mut PgConnection,
+    directory_id: i32,
+) -> Result<usize, diesel::result::Error> {
+    use crate::db::schema::directory::dsl::*;
+
+    let update = diesel::update(directory.filter(id.eq(directory_id))).set(ctime.eq(0));
+
+    let debug = diesel::debug_query::<diesel::pg::Pg, _>(&update);
+    debug!("{}", debug);
+    if dry_run::record("UPDATE", "directory", debug.to_string()) {
+        return Ok(1);
+    }
+    update.execute(c)
+}
+
+/// Check if the table `name` exists in the database.
+///
+/// Used for tables which are not part of every MirrorManager
+/// database like `file_detail_digest`.
+pub fn table_exists(c: &mut PgConnection, name: &str) -> Result<bool, diesel::result::Error> {
+    let query = diesel::sql_query("SELECT to_regclass($1) IS NOT NULL AS exists")
+        .bind::<diesel::sql_types::Text, _>(name);
+    let debug = diesel::debug_query::<diesel::pg::Pg, _>(&query);
+    debug!("{}", debug);
+    Ok(query.get_result::<TableExists>(c)?.exists)
+}
+
+/// Store additional digests of `file_detail` rows in `file_detail_digest`.
+pub fn insert_file_detail_digests(
+    c: &mut PgConnection,
+    digests: &[InsertFileDetailDigest],
+) -> Result<usize, diesel::result::Error> {
+    use crate::db::schema::file_detail_digest::dsl::*;
+
+    let insert = diesel::insert_into(file_detail_digest).values(digests);
+    let debug = diesel::debug_query::<diesel::pg::Pg, _>(&insert);
+    debug!("{}", debug);
+    if dry_run::record("INSERT", "file_detail_digest", debug.to_string()) {
+        return Ok(digests.len());
+    }
     insert.execute(c)
 }
 
+/// Delete the additional digests of the `file_detail` rows `ids`.
+pub fn delete_file_detail_digests(
+    c: &mut PgConnection,
+    ids: &[i32],
+) -> Result<usize, diesel::result::Error> {
+    use crate::db::schema::file_detail_digest::dsl::*;
+
+    let delete = diesel::delete(file_detail_digest.filter(file_detail_id.eq_any(ids)));
+    let debug = diesel::debug_query::<diesel::pg::Pg, _>(&delete);
+    debug!("{}", debug);
+    if dry_run::record("DELETE", "file_detail_digest", debug.to_string()) {
+        return Ok(0);
+    }
+    delete.execute(c)
+}
+
+/// Delete the additional digests of all `file_detail` rows of a directory.
+pub fn delete_directory_file_detail_digests(
+    c: &mut PgConnection,
+    directory_id: i32,
+) -> Result<usize, diesel::result::Error> {
+    use crate::db::schema::file_detail;
+    use crate::db::schema::file_detail_digest::dsl::*;
+
+    let delete = diesel::delete(
+        file_detail_digest.filter(
+            file_detail_id.eq_any(
+                file_detail::table
+                    .select(file_detail::id)
+                    .filter(file_detail::directory_id.eq(directory_id)),
+            ),
+        ),
+    );
+    let debug = diesel::debug_query::<diesel::pg::Pg, _>(&delete);
+    debug!("{}", debug);
+    if dry_run::record("DELETE", "file_detail_digest", debug.to_string()) {
+        return Ok(0);
+    }
+    delete.execute(c)
+}
+
 /// Get the table `file_detail` from the database
 ///
 /// This data is used to store checksum about files. Needef
@@ -89,7 +323,7 @@ pub fn get_file_details(c: &mut PgConnection) -> Vec<FileDetail> {
         sha512,
     ));
     let debug = diesel::debug_query::<diesel::pg::Pg, _>(&query);
-    print_step(debug.to_string());
+    debug!("{}", debug);
     query
         .load::<FileDetail>(c)
         .expect("Error loading file_detail")
@@ -113,7 +347,7 @@ pub fn get_categories(c: &mut PgConnection) -> Vec<Category> {
             category::dsl::product_id,
         ));
     let debug = diesel::debug_query::<diesel::pg::Pg, _>(&query);
-    print_step(debug.to_string());
+    debug!("{}", debug);
     query.load::<Category>(c).expect("Error loading categories")
 }
 
@@ -139,7 +373,7 @@ pub fn get_directories(c: &mut PgConnection, cat_id: i32) -> Vec<Directory> {
         ))
         .filter(directory::dsl::id.eq_any(subselect));
     let debug = diesel::debug_query::<diesel::pg::Pg, _>(&query);
-    print_step(debug.to_string());
+    debug!("{}", debug);
     query
         .load::<Directory>(c)
         .expect("Error loading directories")
@@ -156,7 +390,7 @@ pub fn _get_category_directories(c: &mut PgConnection, cat_id: i32) -> Vec<Categ
         ))
         .filter(category_directory::dsl::category_id.eq(cat_id));
     let debug = diesel::debug_query::<diesel::pg::Pg, _>(&query);
-    print_step(debug.to_string());
+    debug!("{}", debug);
     query
         .load::<CategoryDirectory>(c)
         .expect("Error loading category directories")
@@ -177,6 +411,6 @@ pub fn get_repositories(c: &mut PgConnection) -> Result<Vec<Repository>, diesel:
         disabled,
     ));
     let debug = diesel::debug_query::<diesel::pg::Pg, _>(&query);
-    print_step(debug.to_string());
+    debug!("{}", debug);
     query.load::<Repository>(c)
 }
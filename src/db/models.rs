@@ -1,6 +1,7 @@
 use crate::db::schema::{
-    category, category_directory, directory, file_detail, repository, version,
+    category, category_directory, directory, file_detail, file_detail_digest, repository, version,
 };
+use std::collections::BTreeMap;
 
 #[derive(Queryable, Identifiable, Associations)]
 #[diesel(belongs_to(Directory, foreign_key = topdir_id))]
@@ -31,7 +32,7 @@ pub struct CategoryDirectory {
     pub directory_id: i32,
 }
 
-#[derive(Queryable)]
+#[derive(Queryable, Clone)]
 pub struct Arch {
     pub id: i32,
     pub name: String,
@@ -95,4 +96,15 @@ pub struct InsertFileDetail {
     pub md5: Option<String>,
     pub sha256: Option<String>,
     pub sha512: Option<String>,
+    /// Additional digests by algorithm for `file_detail_digest`
+    #[diesel(skip_insertion)]
+    pub digests: BTreeMap<String, String>,
+}
+
+#[derive(Insertable, Debug)]
+#[diesel(table_name = file_detail_digest)]
+pub struct InsertFileDetailDigest {
+    pub file_detail_id: i32,
+    pub algorithm: String,
+    pub digest: String,
 }
@@ -71,6 +71,16 @@ table! {
     }
 }
 
+table! {
+    /// Optional table with additional digests of a `file_detail` row.
+    /// It is not part of the MirrorManager schema.
+    file_detail_digest (file_detail_id, algorithm) {
+        file_detail_id -> Integer,
+        algorithm -> Text,
+        digest -> Text,
+    }
+}
+
 table! {
     host_category_dir (id) {
         id -> Integer,
@@ -82,3 +92,4 @@ joinable!(category -> directory (topdir_id));
 
 allow_tables_to_appear_in_same_query!(category, directory);
 allow_tables_to_appear_in_same_query!(category_directory, directory);
+allow_tables_to_appear_in_same_query!(file_detail, file_detail_digest);
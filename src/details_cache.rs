@@ -0,0 +1,140 @@
+// SPDX-License-Identifier: MIT
+
+use crate::digests;
+use crate::http::Validators;
+use crate::DetailsResult;
+
+use log::warn;
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+/// Entries not used for this many seconds are dropped.
+const MAX_UNUSED_SECONDS: i64 = 30 * 24 * 60 * 60;
+
+/// The details computed from a downloaded file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Entry {
+    /// The validators of the response the details were computed from
+    pub validators: Validators,
+    /// The `extra_digests` which were computed
+    pub extra: Vec<String>,
+    /// The fingerprint of the key which signed a checksum file
+    #[serde(default)]
+    pub signer: Option<String>,
+    pub details: Vec<DetailsResult>,
+    /// The last time (seconds since the epoch) this entry was used
+    used: i64,
+}
+
+/// Details of files downloaded during this or earlier runs, keyed by URL.
+///
+/// Shared by all categories scanned in parallel. It is only
+/// written to disk at the end of the run.
+#[derive(Debug, Default)]
+pub struct Cache {
+    path: Option<PathBuf>,
+    entries: Mutex<HashMap<String, Entry>>,
+    changed: AtomicBool,
+}
+
+fn extra_names(extra: &[digests::Extra]) -> Vec<String> {
+    extra.iter().map(|e| e.name().to_string()).collect()
+}
+
+impl Cache {
+    /// Read the cache written by an earlier run from `path`.
+    ///
+    /// A missing or unreadable cache file results in an empty cache.
+    /// Without a `path` the cache only lives as long as this run.
+    pub fn load(path: Option<&str>) -> Cache {
+        let path = match path {
+            Some(p) => PathBuf::from(p),
+            None => return Cache::default(),
+        };
+
+        let entries = match std::fs::read(&path) {
+            Ok(data) => serde_json::from_slice(&data).unwrap_or_else(|e| {
+                warn!("Ignoring details cache {}: {}", path.display(), e);
+                HashMap::new()
+            }),
+            Err(_) => HashMap::new(),
+        };
+
+        Cache {
+            path: Some(path),
+            entries: Mutex::new(entries),
+            changed: AtomicBool::new(false),
+        }
+    }
+
+    /// Write the cache if it has changed.
+    ///
+    /// Entries which have not been used for 30 days are dropped.
+    pub fn save(&self) -> Result<(), Box<dyn Error>> {
+        let path = match &self.path {
+            Some(p) => p,
+            None => return Ok(()),
+        };
+        let mut entries = self.entries.lock().unwrap();
+        let count = entries.len();
+        let now = chrono::Utc::now().timestamp();
+        entries.retain(|_, e| now - e.used < MAX_UNUSED_SECONDS);
+        if !self.changed.load(Ordering::SeqCst) && count == entries.len() {
+            return Ok(());
+        }
+
+        let mut json = serde_json::to_string(&*entries)?;
+        json.push('\n');
+        crate::filelist::write_if_changed(path, &json)?;
+
+        Ok(())
+    }
+
+    /// Returns the cached details of `url`.
+    ///
+    /// Entries computed with other `extra` digests are not returned.
+    pub fn get(&self, url: &str, extra: &[digests::Extra]) -> Option<Entry> {
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries.get_mut(url)?;
+        if entry.extra != extra_names(extra) {
+            return None;
+        }
+        entry.used = chrono::Utc::now().timestamp();
+        self.changed.store(true, Ordering::SeqCst);
+
+        Some(entry.clone())
+    }
+
+    /// Remember the `details` of `url` computed with the `extra` digests
+    /// from the response with `validators`. `signer` is the key which
+    /// signed the downloaded checksum file.
+    ///
+    /// Without validators the details cannot be used for a conditional
+    /// request and are not cached.
+    pub fn insert(
+        &self,
+        url: &str,
+        validators: Validators,
+        extra: &[digests::Extra],
+        signer: Option<String>,
+        details: &[DetailsResult],
+    ) {
+        if validators.is_empty() {
+            return;
+        }
+        self.entries.lock().unwrap().insert(
+            url.to_string(),
+            Entry {
+                validators,
+                extra: extra_names(extra),
+                signer,
+                details: details.to_vec(),
+                used: chrono::Utc::now().timestamp(),
+            },
+        );
+        self.changed.store(true, Ordering::SeqCst);
+    }
+}
@@ -0,0 +1,162 @@
+// SPDX-License-Identifier: MIT
+
+use blake2::Blake2b512;
+use md5::{Digest, Md5};
+use sha1::Sha1;
+use sha2::{Sha256, Sha384, Sha512};
+use sha3::Sha3_256;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::io::Read;
+
+/// Digest algorithms which can be computed in addition to the ones
+/// stored in the `file_detail` table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Extra {
+    Sha384,
+    Sha3_256,
+    /// BLAKE2b with a 512 bit digest
+    Blake2b,
+}
+
+impl Extra {
+    /// The name used in the configuration file and the database
+    pub fn name(self) -> &'static str {
+        match self {
+            Extra::Sha384 => "sha384",
+            Extra::Sha3_256 => "sha3-256",
+            Extra::Blake2b => "blake2b",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Extra> {
+        [Extra::Sha384, Extra::Sha3_256, Extra::Blake2b]
+            .iter()
+            .copied()
+            .find(|e| e.name() == name)
+    }
+}
+
+/// Parse the names of the `extra_digests` from the configuration file.
+pub fn extra_algorithms(names: &[String]) -> Result<Vec<Extra>, Box<dyn Error>> {
+    let mut extra: Vec<Extra> = Vec::new();
+    for name in names {
+        match Extra::from_name(name) {
+            Some(e) if !extra.contains(&e) => extra.push(e),
+            Some(_) => (),
+            None => {
+                return Err(format!(
+                    "Unknown digest algorithm '{}' (known: sha384, sha3-256, blake2b)",
+                    name
+                )
+                .into())
+            }
+        }
+    }
+
+    Ok(extra)
+}
+
+enum ExtraHasher {
+    Sha384(Sha384),
+    Sha3_256(Sha3_256),
+    Blake2b(Blake2b512),
+}
+
+impl ExtraHasher {
+    fn new(extra: Extra) -> ExtraHasher {
+        match extra {
+            Extra::Sha384 => ExtraHasher::Sha384(Sha384::new()),
+            Extra::Sha3_256 => ExtraHasher::Sha3_256(Sha3_256::new()),
+            Extra::Blake2b => ExtraHasher::Blake2b(Blake2b512::new()),
+        }
+    }
+
+    fn update(&mut self, block: &[u8]) {
+        match self {
+            ExtraHasher::Sha384(h) => h.update(block),
+            ExtraHasher::Sha3_256(h) => h.update(block),
+            ExtraHasher::Blake2b(h) => h.update(block),
+        }
+    }
+
+    fn finalize(self) -> String {
+        match self {
+            ExtraHasher::Sha384(h) => to_hex(&h.finalize()),
+            ExtraHasher::Sha3_256(h) => to_hex(&h.finalize()),
+            ExtraHasher::Blake2b(h) => to_hex(&h.finalize()),
+        }
+    }
+}
+
+/// The checksums stored in `file_detail` for a file.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Digests {
+    pub md5: String,
+    pub sha1: String,
+    pub sha256: String,
+    pub sha512: String,
+    /// Number of bytes read
+    pub length: i64,
+    /// The requested `Extra` digests by name
+    #[serde(default)]
+    pub extra: BTreeMap<String, String>,
+}
+
+fn to_hex(hash: &[u8]) -> String {
+    hash.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Compute all digests of the data from `reader` in one pass.
+///
+/// The data is read in blocks and never held in memory completely.
+/// If `keep` is given the data is also appended to it for callers
+/// which need to parse the content (`repomd.xml`). The `extra`
+/// digests are computed in the same pass.
+pub fn from_reader(
+    reader: &mut dyn Read,
+    mut keep: Option<&mut Vec<u8>>,
+    extra: &[Extra],
+) -> Result<Digests, std::io::Error> {
+    let mut extra_hashers: Vec<(Extra, ExtraHasher)> =
+        extra.iter().map(|e| (*e, ExtraHasher::new(*e))).collect();
+    let mut md5 = Md5::new();
+    let mut sha1 = Sha1::new();
+    let mut sha256 = Sha256::new();
+    let mut sha512 = Sha512::new();
+    let mut length: i64 = 0;
+    let mut buffer = vec![0u8; 64 * 1024];
+
+    loop {
+        let count = match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(c) => c,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        let block = &buffer[..count];
+        md5.update(block);
+        sha1.update(block);
+        sha256.update(block);
+        sha512.update(block);
+        for (_, h) in extra_hashers.iter_mut() {
+            h.update(block);
+        }
+        if let Some(k) = keep.as_mut() {
+            k.extend_from_slice(block);
+        }
+        length += count as i64;
+    }
+
+    Ok(Digests {
+        md5: to_hex(&md5.finalize()),
+        sha1: to_hex(&sha1.finalize()),
+        sha256: to_hex(&sha256.finalize()),
+        sha512: to_hex(&sha512.finalize()),
+        length,
+        extra: extra_hashers
+            .into_iter()
+            .map(|(e, h)| (e.name().to_string(), h.finalize()))
+            .collect(),
+    })
+}
@@ -0,0 +1,93 @@
+// SPDX-License-Identifier: MIT
+
+use prettytable::format;
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// IDs handed out for rows which are not really inserted.
+/// Negative to never collide with existing rows.
+static NEXT_ID: AtomicI32 = AtomicI32::new(-1);
+
+thread_local! {
+    /// The changes of the category currently scanned on this thread.
+    static CHANGES: RefCell<Vec<Change>> = const { RefCell::new(Vec::new()) };
+}
+
+/// A database change which would have been done without `--dry-run`.
+#[derive(Debug, Clone, Serialize)]
+pub struct Change {
+    /// INSERT, UPDATE or DELETE
+    pub operation: String,
+    pub table: String,
+    pub statement: String,
+}
+
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::SeqCst);
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::SeqCst)
+}
+
+/// Record a database change.
+///
+/// Returns `true` if running in dry-run mode. In that case the
+/// statement must not be executed.
+pub fn record(operation: &str, table: &str, statement: String) -> bool {
+    if !is_enabled() {
+        return false;
+    }
+
+    CHANGES.with(|c| {
+        c.borrow_mut().push(Change {
+            operation: operation.to_string(),
+            table: table.to_string(),
+            statement,
+        })
+    });
+
+    true
+}
+
+/// Returns an ID for a row which has not really been inserted.
+pub fn next_id() -> i32 {
+    NEXT_ID.fetch_sub(1, Ordering::SeqCst)
+}
+
+/// Returns all recorded changes and resets the list.
+pub fn take() -> Vec<Change> {
+    CHANGES.with(|c| c.borrow_mut().drain(..).collect())
+}
+
+/// Print the changes of each category as a table.
+pub fn print_table(changes: &[(String, Vec<Change>)]) {
+    for (category, category_changes) in changes {
+        println!("Planned database changes for category '{}'", category);
+
+        let mut table = prettytable::Table::new();
+        table.set_format(*format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
+
+        table.set_titles(row!["Operation", "Table", "Statement"]);
+
+        for c in category_changes {
+            table.add_row(row![c.operation, c.table, c.statement]);
+        }
+
+        table.printstd();
+        println!("{} database changes planned", category_changes.len());
+    }
+}
+
+/// Print the changes of all categories as a JSON object
+/// using the category name as key.
+pub fn print_json(changes: &[(String, Vec<Change>)]) {
+    let map: std::collections::BTreeMap<&String, &Vec<Change>> =
+        changes.iter().map(|(k, v)| (k, v)).collect();
+    match serde_json::to_string_pretty(&map) {
+        Ok(j) => println!("{}", j),
+        Err(e) => println!("Creating JSON failed {}", e),
+    }
+}
@@ -0,0 +1,132 @@
+// SPDX-License-Identifier: MIT
+
+use crate::CategoryDirectory;
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::Path;
+
+/// Returns true for files which are listed in an `imagelist-*`.
+fn is_image(name: &str) -> bool {
+    [".iso", ".qcow2", ".raw.xz", ".box"]
+        .iter()
+        .any(|e| name.ends_with(e))
+}
+
+/// The generated files are not part of the lists themselves. Otherwise
+/// each run would change the lists again.
+fn is_filelist(name: &str) -> bool {
+    name.starts_with("fullfiletimelist-") || name.starts_with("imagelist-")
+}
+
+fn join(prefix: &str, dir: &str, name: &str) -> String {
+    match dir.is_empty() {
+        true => format!("{}{}", prefix, name),
+        false => format!("{}{}/{}", prefix, dir, name),
+    }
+}
+
+/// Create the content of a `fullfiletimelist-*` from a scan.
+///
+/// Each entry is written as tab separated timestamp, type, size and path
+/// just like it is read by `scan_local_directory()`. Entries in not
+/// readable directories are marked with a '-' after the type.
+/// `prefix` is prepended to each path.
+pub fn fullfiletimelist(cds: &HashMap<String, CategoryDirectory>, prefix: &str) -> String {
+    let mut entries: Vec<(String, String)> = Vec::new();
+
+    for (k, cd) in cds {
+        let restricted = match cd.readable {
+            true => "",
+            false => "-",
+        };
+        let path = match k.is_empty() {
+            true => format!("{}.", prefix),
+            false => format!("{}{}", prefix, k),
+        };
+        entries.push((
+            path.clone(),
+            format!("{}\td{}\t0\t{}", cd.ctime, restricted, path),
+        ));
+
+        for f in &cd.files {
+            if is_filelist(&f.name) {
+                continue;
+            }
+            let path = join(prefix, k, &f.name);
+            entries.push((
+                path.clone(),
+                format!("{}\tf{}\t{}\t{}", f.timestamp, restricted, f.size, path),
+            ));
+        }
+    }
+
+    entries.sort();
+
+    let mut content = String::from("[Version]\n3\n\n[Files]\n");
+    for (_, line) in entries {
+        content.push_str(&line);
+        content.push('\n');
+    }
+    content.push_str("\n[End]\n");
+
+    content
+}
+
+/// Create the content of an `imagelist-*` from a scan.
+///
+/// This is the sorted list of all images (ISOs, cloud and
+/// vagrant images) with `prefix` prepended to each path.
+pub fn imagelist(cds: &HashMap<String, CategoryDirectory>, prefix: &str) -> String {
+    let mut images: Vec<String> = Vec::new();
+
+    for (k, cd) in cds {
+        for f in &cd.files {
+            if is_image(&f.name) {
+                images.push(join(prefix, k, &f.name));
+            }
+        }
+    }
+
+    images.sort();
+
+    let mut content = String::new();
+    for i in images {
+        content.push_str(&i);
+        content.push('\n');
+    }
+
+    content
+}
+
+/// Returns true if `content` differs from the existing file at `path`.
+pub fn is_changed(path: &Path, content: &str) -> bool {
+    match std::fs::read(path) {
+        Ok(existing) => existing != content.as_bytes(),
+        _ => true,
+    }
+}
+
+/// Write `content` to `path` if it differs from the existing file.
+///
+/// The file is written to a temporary file in the same directory
+/// which is then renamed to `path`. Readers will always either see
+/// the old or the new file. Returns true if the file was written.
+pub fn write_if_changed(path: &Path, content: &str) -> Result<bool, Box<dyn Error>> {
+    if !is_changed(path, content) {
+        return Ok(false);
+    }
+
+    let file_name = match path.file_name() {
+        Some(f) => f.to_string_lossy().to_string(),
+        _ => return Err(format!("Cannot write to '{}'", path.display()).into()),
+    };
+    let tmp = path.with_file_name(format!(".{}.tmp-{}", file_name, std::process::id()));
+    std::fs::write(&tmp, content)?;
+    if let Err(e) = std::fs::rename(&tmp, path) {
+        std::fs::remove_file(&tmp)?;
+        return Err(e.into());
+    }
+
+    Ok(true)
+}
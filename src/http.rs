@@ -0,0 +1,220 @@
+// SPDX-License-Identifier: MIT
+
+use crate::settings;
+
+use log::warn;
+use reqwest::blocking::{RequestBuilder, Response};
+use reqwest::{header, Method};
+use std::error::Error;
+use std::io::Read;
+use std::time::Duration;
+
+/// The User-Agent header if none is configured.
+pub const DEFAULT_USER_AGENT: &str =
+    concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+
+enum Auth {
+    Basic(String, String),
+    Bearer(String),
+}
+
+/// The `ETag` and `Last-Modified` headers of a response. They are
+/// sent with the next request to only download the file if it changed.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Validators {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+impl Validators {
+    /// The validators of the response `r`.
+    pub fn from_response(r: &Response) -> Validators {
+        let get = |name| {
+            r.headers()
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_string)
+        };
+
+        Validators {
+            etag: get(header::ETAG),
+            last_modified: get(header::LAST_MODIFIED),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.etag.is_none() && self.last_modified.is_none()
+    }
+}
+
+/// Read the complete body of `response`.
+///
+/// Unlike `Response::bytes()` the read timeout of the client only
+/// limits the time to wait for each block of data and not the time
+/// of the whole download.
+pub fn read_body(mut response: Response) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut body = Vec::new();
+    response.read_to_end(&mut body)?;
+
+    Ok(body)
+}
+
+/// An HTTP(S) client built from the `[http]` settings.
+///
+/// Requests are retried with an exponential backoff after
+/// connection errors, timeouts and 5xx responses.
+pub struct Client {
+    client: reqwest::blocking::Client,
+    auth: Option<Auth>,
+    retries: u32,
+    retry_backoff: Duration,
+}
+
+/// Read a password or token from `path` without the trailing line break.
+fn read_secret(path: &str, what: &str) -> Result<String, Box<dyn Error>> {
+    let secret = std::fs::read_to_string(path)
+        .map_err(|e| format!("Reading {} file {} failed: {}", what, path, e))?;
+
+    Ok(secret.trim_end_matches(['\r', '\n']).to_string())
+}
+
+impl Client {
+    /// Create the client for `settings`.
+    ///
+    /// Fails if one of the configured files cannot be read or
+    /// does not contain what is expected.
+    pub fn new(settings: &settings::Http) -> Result<Client, Box<dyn Error>> {
+        let mut builder = reqwest::blocking::Client::builder()
+            .user_agent(settings.user_agent.as_deref().unwrap_or(DEFAULT_USER_AGENT));
+
+        if let Some(t) = settings.connect_timeout {
+            builder = builder.connect_timeout(Duration::from_secs(t));
+        }
+        // The blocking client applies this timeout to waiting for the
+        // response and to each read of the body, but `text()` and `bytes()`
+        // would limit reading the whole body. Use `read_body()` instead.
+        builder = builder.timeout(Duration::from_secs(settings.read_timeout.unwrap_or(30)));
+        if let Some(ca_file) = &settings.ca_file {
+            let pem = std::fs::read(ca_file)
+                .map_err(|e| format!("Reading CA file {} failed: {}", ca_file, e))?;
+            let certificates = reqwest::Certificate::from_pem_bundle(&pem)
+                .map_err(|e| format!("Parsing CA file {} failed: {}", ca_file, e))?;
+            if certificates.is_empty() {
+                return Err(
+                    format!("CA file {} does not contain any certificates", ca_file).into(),
+                );
+            }
+            for c in certificates {
+                builder = builder.add_root_certificate(c);
+            }
+        }
+        if let Some(client_cert) = &settings.client_cert {
+            let pem = std::fs::read(client_cert)
+                .map_err(|e| format!("Reading client certificate {} failed: {}", client_cert, e))?;
+            let identity = reqwest::Identity::from_pem(&pem)
+                .map_err(|e| format!("Parsing client certificate {} failed: {}", client_cert, e))?;
+            builder = builder.identity(identity);
+        }
+        if let Some(proxy) = &settings.proxy {
+            builder = builder.proxy(
+                reqwest::Proxy::all(proxy)
+                    .map_err(|e| format!("Invalid proxy {}: {}", proxy, e))?,
+            );
+        }
+
+        let auth = match (&settings.token_file, &settings.username) {
+            (Some(_), Some(_)) => {
+                return Err("Only one of 'token_file' and 'username' can be set".into())
+            }
+            (Some(token_file), None) => Some(Auth::Bearer(read_secret(token_file, "token")?)),
+            (None, Some(username)) => Some(Auth::Basic(
+                username.to_string(),
+                match &settings.password_file {
+                    Some(p) => read_secret(p, "password")?,
+                    None => return Err("'username' requires 'password_file'".into()),
+                },
+            )),
+            (None, None) => None,
+        };
+
+        Ok(Client {
+            client: builder.build()?,
+            auth,
+            retries: settings.retries.unwrap_or(0),
+            retry_backoff: Duration::from_secs(settings.retry_backoff.unwrap_or(1)),
+        })
+    }
+
+    fn authenticate(&self, request: RequestBuilder) -> RequestBuilder {
+        match &self.auth {
+            Some(Auth::Basic(user, password)) => request.basic_auth(user, Some(password)),
+            Some(Auth::Bearer(token)) => request.bearer_auth(token),
+            None => request,
+        }
+    }
+
+    /// Send a GET request for `url`.
+    ///
+    /// Returns the response of the last attempt. Only a response
+    /// with a 5xx status is retried, all other responses are
+    /// returned as they are.
+    pub fn get(&self, url: &str) -> Result<Response, Box<dyn Error>> {
+        self.get_if_modified(url, &Validators::default())
+    }
+
+    /// Send a conditional GET request for `url`.
+    ///
+    /// Like `get()` but the server answers with `304 Not Modified`
+    /// if the file has not changed since the response `validators`
+    /// were taken from.
+    pub fn get_if_modified(
+        &self,
+        url: &str,
+        validators: &Validators,
+    ) -> Result<Response, Box<dyn Error>> {
+        self.send(Method::GET, url, validators)
+    }
+
+    /// Send a HEAD request for `url`.
+    ///
+    /// Retried like `get()`.
+    pub fn head(&self, url: &str) -> Result<Response, Box<dyn Error>> {
+        self.send(Method::HEAD, url, &Validators::default())
+    }
+
+    fn send(
+        &self,
+        method: Method,
+        url: &str,
+        validators: &Validators,
+    ) -> Result<Response, Box<dyn Error>> {
+        let mut attempt = 0;
+        loop {
+            let mut request = self.authenticate(self.client.request(method.clone(), url));
+            if let Some(etag) = &validators.etag {
+                request = request.header(header::IF_NONE_MATCH, etag);
+            }
+            if let Some(last_modified) = &validators.last_modified {
+                request = request.header(header::IF_MODIFIED_SINCE, last_modified);
+            }
+            let problem = match request.send() {
+                Ok(r) if r.status().is_server_error() && attempt < self.retries => {
+                    format!("status '{}'", r.status())
+                }
+                Ok(r) => return Ok(r),
+                Err(e) if (e.is_connect() || e.is_timeout()) && attempt < self.retries => {
+                    format!("error '{}'", e)
+                }
+                Err(e) => return Err(format!("Error '{}' retrieving '{}'", e, url).into()),
+            };
+
+            let wait = self.retry_backoff * 2u32.pow(attempt.min(16));
+            attempt += 1;
+            warn!(
+                "Retrieving '{}' failed with {}, retry {} of {} in {:?}",
+                url, problem, attempt, self.retries, wait
+            );
+            std::thread::sleep(wait);
+        }
+    }
+}
@@ -0,0 +1,90 @@
+// SPDX-License-Identifier: MIT
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use std::cell::RefCell;
+use std::io::Write;
+
+thread_local! {
+    /// The category currently scanned on this thread.
+    static CATEGORY: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Writes log records as text or JSON lines to stderr.
+struct Logger {
+    json: bool,
+}
+
+static TEXT_LOGGER: Logger = Logger { json: false };
+static JSON_LOGGER: Logger = Logger { json: true };
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        // Only errors and warnings from other crates (reqwest, rustls, ...)
+        metadata.level() <= log::max_level()
+            && (metadata.level() <= Level::Warn
+                || metadata
+                    .target()
+                    .starts_with(module_path!().split("::").next().unwrap()))
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let category = CATEGORY.with(|c| c.borrow().clone());
+        let line = match self.json {
+            true => serde_json::json!({
+                "timestamp": chrono::offset::Local::now().to_rfc3339(),
+                "level": record.level().as_str(),
+                "target": record.target(),
+                "category": category,
+                "message": record.args().to_string(),
+            })
+            .to_string(),
+            false => match category {
+                Some(c) => format!("{:5} [{}] {}", record.level(), c, record.args()),
+                _ => format!("{:5} {}", record.level(), record.args()),
+            },
+        };
+
+        let _ = writeln!(std::io::stderr().lock(), "{}", line);
+    }
+
+    fn flush(&self) {
+        let _ = std::io::stderr().flush();
+    }
+}
+
+/// Map the number of `-d` options to the log level.
+pub fn level(verbosity: usize) -> LevelFilter {
+    match verbosity {
+        0 => LevelFilter::Info,
+        1 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    }
+}
+
+/// Set up logging to stderr.
+///
+/// * `verbosity` - number of `-d` options given
+/// * `json` - write each record as a JSON object on its own line
+pub fn init(verbosity: usize, json: bool) {
+    let logger = match json {
+        true => &JSON_LOGGER,
+        false => &TEXT_LOGGER,
+    };
+    if log::set_logger(logger).is_ok() {
+        log::set_max_level(level(verbosity));
+    }
+}
+
+/// Add the name of the category scanned on this thread to all records.
+pub fn set_category(name: Option<&str>) {
+    CATEGORY.with(|c| *c.borrow_mut() = name.map(str::to_string));
+}
+
+/// The name of the category scanned on this thread.
+pub fn category() -> Option<String> {
+    CATEGORY.with(|c| c.borrow().clone())
+}
@@ -13,26 +13,43 @@ extern crate config;
 #[macro_use]
 extern crate serde_derive;
 
+mod backend;
+mod checksum;
 mod db;
-mod debug;
+mod details_cache;
+mod digests;
+mod dry_run;
+mod filelist;
+mod http;
+mod logging;
+mod metrics;
+mod report;
+mod rsync;
 mod settings;
+mod signature;
+mod verify;
 mod xml;
 
+use backend::ScanBackend;
 use settings::Settings;
 
 use regex::Regex;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::env;
 use std::error::Error;
-use std::path::Path;
+use std::io::Read;
+use std::path::{Path, PathBuf};
 use std::process;
 use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
 
 use prettytable::format;
 
 use diesel::pg::PgConnection;
 use diesel::prelude::*;
 use getopts::Options;
+use log::{debug, error, info, trace, warn};
 
 use chrono::prelude::*;
 
@@ -90,6 +107,7 @@ struct UpdateDirectory {
 ///   be propagated to all mirrors.
 /// * `fds` - The list of all file_details which is used as the basis
 ///   for the clean up operation.
+/// * `digest_table` - also delete the rows in `file_detail_digest`
 ///
 /// # Further Details
 ///
@@ -110,6 +128,7 @@ fn age_file_details(
     c: &mut PgConnection,
     fds: &mut Vec<db::models::FileDetail>,
     dirs: &[db::models::Directory],
+    digest_table: bool,
     max_stale_days: i64,
     max_propagation_days: i64,
 ) -> Result<(), diesel::result::Error> {
@@ -164,16 +183,37 @@ fn age_file_details(
     }
 
     if !delete_list.is_empty() {
-        debug::STEPS.fetch_add(delete_list.len(), std::sync::atomic::Ordering::SeqCst);
+        info!("Aging {} file_detail entries", delete_list.len());
+    }
+    delete_file_details(c, &delete_list, digest_table, report::aged_file_detail)
+}
+
+/// Delete the `file_detail` entries `ids` (and their rows in
+/// `file_detail_digest` if `digest_table` is set).
+///
+/// Each deleted entry is recorded with `record` which tells the
+/// report why it has been deleted.
+fn delete_file_details(
+    c: &mut PgConnection,
+    ids: &[i32],
+    digest_table: bool,
+    record: fn(i32),
+) -> Result<(), diesel::result::Error> {
+    if digest_table && !ids.is_empty() {
+        db::functions::delete_file_detail_digests(c, ids)?;
     }
 
-    for d in delete_list {
+    for d in ids {
         let delete = diesel::delete(
             db::schema::file_detail::dsl::file_detail
                 .filter(db::schema::file_detail::dsl::id.eq(d)),
         );
         let debug = diesel::debug_query::<diesel::pg::Pg, _>(&delete);
-        debug::print_step(debug.to_string());
+        debug!("{}", debug);
+        record(*d);
+        if dry_run::record("DELETE", "file_detail", debug.to_string()) {
+            continue;
+        }
         delete.execute(c)?;
     }
 
@@ -257,7 +297,7 @@ fn guess_ver_arch_from_path(
     }
 
     if arch_id == -1 {
-        println!("Not able to figure out architecture from {}", path);
+        warn!("Not able to figure out architecture from {}", path);
         return Err("Not able to figure out architecture".into());
     }
 
@@ -282,6 +322,15 @@ fn guess_ver_arch_from_path(
     if version_id == -1 {
         let version = get_version_from_path(&path);
         if !version.is_empty() {
+            // Another scan running in parallel might have just created this version.
+            db::functions::lock_product(c, product_id)?;
+            if let Some(v) = db::functions::get_version(c, product_id, &version)? {
+                version_id = v.id;
+                version_name = v.name.clone();
+                versions.push(v);
+                return Ok((version_name, version_id, arch_id));
+            }
+
             // Version does not exist yet in the database. Let's create it
             let mut is_test = false;
             let mut display = true;
@@ -299,17 +348,27 @@ fn guess_ver_arch_from_path(
             }
             let insert = diesel::insert_into(db::schema::version::dsl::version).values((
                 db::schema::version::dsl::product_id.eq(product_id),
-                db::schema::version::dsl::name.eq(version),
+                db::schema::version::dsl::name.eq(&version),
                 db::schema::version::dsl::sortorder.eq(0),
                 db::schema::version::dsl::is_test.eq(is_test),
                 db::schema::version::dsl::display.eq(display),
                 db::schema::version::dsl::ordered_mirrorlist.eq(true),
             ));
-            debug::STEPS.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
 
             let debug = diesel::debug_query::<diesel::pg::Pg, _>(&insert);
-            debug::print_step(debug.to_string());
-            let result = insert.get_results::<db::models::InsertVersion>(c)?;
+            debug!("{}", debug);
+            let result = match dry_run::record("INSERT", "version", debug.to_string()) {
+                true => vec![db::models::InsertVersion {
+                    id: dry_run::next_id(),
+                    name: version,
+                    product_id,
+                    is_test,
+                    sortorder: 0,
+                    display,
+                    ordered_mirrorlist: true,
+                }],
+                false => insert.get_results::<db::models::InsertVersion>(c)?,
+            };
 
             versions.push(db::models::Version {
                 id: result[0].id,
@@ -319,6 +378,7 @@ fn guess_ver_arch_from_path(
             });
             version_id = result[0].id;
             version_name = result[0].name.clone();
+            report::created_version(&version_name);
         }
     }
 
@@ -351,7 +411,7 @@ fn repo_prefix(
         let pattern = match Regex::new(rm.regex.as_str()) {
             Ok(p) => p,
             Err(_) => {
-                println!("Cannot handle regex {}. Skipping", rm.regex);
+                warn!("Cannot handle regex {}. Skipping", rm.regex);
                 continue;
             }
         };
@@ -411,87 +471,230 @@ fn check_for_repo(repos: &[db::models::Repository], prefix: String, arch_id: i32
     false
 }
 
-/// Remove non-existing directories from the database
+/// The detached repositories of the product `product_id`
 ///
-/// If a directory has been deleted on the file system it will still exist in the database. This
-/// function goes through the list of database directories and if it does not exist on the file
-/// system it will be removed from `category_directory`, `host_category_dir`, `directory`,
-/// `repository' and `file_detail`.
-fn cleanup_database(
-    c: &mut PgConnection,
-    cds: &HashMap<String, CategoryDirectory>,
-    dirs: &[db::models::Directory],
-    topdir: String,
-) -> Result<usize, diesel::result::Error> {
-    for d in dirs {
-        let mut dir_gone_from_fs = true;
+/// Repositories are detached from their directory instead of being
+/// deleted if `soft_delete_repositories` is set.
+fn detached_repos<'a>(
+    repos: &'a [db::models::Repository],
+    versions: &[db::models::Version],
+    product_id: i32,
+) -> Vec<&'a db::models::Repository> {
+    repos
+        .iter()
+        .filter(|r| {
+            r.directory_id.is_none()
+                && versions
+                    .iter()
+                    .any(|v| Some(v.id) == r.version_id && v.product_id == product_id)
+        })
+        .collect()
+}
+
+/// Find one of the `detached` repositories for this prefix and architecture
+fn detached_repo<'a>(
+    detached: &[&'a db::models::Repository],
+    prefix: &str,
+    arch_id: i32,
+) -> Option<&'a db::models::Repository> {
+    detached
+        .iter()
+        .find(|r| r.prefix.as_deref() == Some(prefix) && r.arch_id == Some(arch_id))
+        .copied()
+}
+
+/// Check if one of the `detached` repositories could have moved to `path`.
+///
+/// Only the architecture and the version of the repositories are
+/// looked for in `path` as this does not need the database.
+fn may_have_moved(
+    path: &str,
+    detached: &[&db::models::Repository],
+    arches: &[db::models::Arch],
+    versions: &[db::models::Version],
+) -> bool {
+    let in_path = |name: &str| {
+        Regex::new(format!(r"(^|/){}(/|$)", regex::escape(name)).as_str())
+            .unwrap()
+            .is_match(path)
+    };
 
-        for k in cds.keys() {
+    detached.iter().any(|r| {
+        let arch = arches.iter().find(|a| Some(a.id) == r.arch_id);
+        let version = versions.iter().find(|v| Some(v.id) == r.version_id);
+        match (arch, version) {
+            (Some(a), Some(v)) => {
+                (in_path(&a.name)
+                    || a.name == "source" && (path.contains("SRPMS") || path.contains("/src")))
+                    && in_path(match v.name.as_str() {
+                        "development" => "rawhide",
+                        name => name,
+                    })
+            }
+            _ => false,
+        }
+    })
+}
+
+/// Returns the directories in `dirs` which have not been found by the scan.
+fn gone_directories<'a>(
+    cds: &HashMap<String, CategoryDirectory>,
+    dirs: &'a [db::models::Directory],
+    topdir: &str,
+) -> Vec<&'a db::models::Directory> {
+    let scanned: HashSet<String> = cds
+        .keys()
+        .map(|k| {
             let mut with_topdir = format!("{}{}", topdir, k);
             if k.is_empty() {
                 with_topdir.pop();
             }
+            with_topdir
+        })
+        .collect();
 
-            if d.name == with_topdir {
-                dir_gone_from_fs = false;
-                break;
-            }
-        }
+    dirs.iter().filter(|d| !scanned.contains(&d.name)).collect()
+}
 
-        if dir_gone_from_fs {
-            debug::STEPS.fetch_add(5, std::sync::atomic::Ordering::SeqCst);
-            // Delete from CategoryDirectory (Is it possible to delete multiple entries at once???)
-            // Something like 'DELETE FROM category_directory where directory_id = 10 or directory_id = 20'.
-            let delete_cd = diesel::delete(
-                db::schema::category_directory::dsl::category_directory
-                    .filter(db::schema::category_directory::dsl::directory_id.eq(d.id)),
-            );
-            let debug_cd = diesel::debug_query::<diesel::pg::Pg, _>(&delete_cd);
-            debug::print_step(debug_cd.to_string());
+/// Check that deleting `gone` stays within the safety limits.
+///
+/// `total` is the number of directories of the category in the
+/// database before the scan added new ones. If more than `max_count`
+/// directories or more than `max_percent` percent of the directories
+/// would be deleted, all of them are logged and an error is returned
+/// unless `force` is set.
+fn check_delete_limits(
+    gone: &[&db::models::Directory],
+    total: usize,
+    max_count: usize,
+    max_percent: f64,
+    force: bool,
+) -> Result<(), String> {
+    let percent = match total {
+        0 => 0.0,
+        _ => gone.len() as f64 * 100.0 / total as f64,
+    };
+    let mut exceeded: Vec<String> = Vec::new();
+    if gone.len() > max_count {
+        exceeded.push(format!("more than {} directories", max_count));
+    }
+    if percent > max_percent {
+        exceeded.push(format!("more than {}% of the directories", max_percent));
+    }
+    if exceeded.is_empty() {
+        return Ok(());
+    }
+
+    let problem = format!(
+        "Deleting {} of {} directories ({:.1}%) exceeds the limit of {}",
+        gone.len(),
+        total,
+        percent,
+        exceeded.join(" and ")
+    );
+    if force {
+        warn!("{}. Deleting them anyway because of --force.", problem);
+        return Ok(());
+    }
+
+    for d in gone {
+        warn!("Would delete directory {}", d.name);
+    }
+
+    Err(format!("{}. Use --force to delete them.", problem))
+}
+
+/// Remove non-existing directories from the database
+///
+/// If a directory has been deleted on the file system it will still exist in the database. This
+/// function removes each directory of `gone` (see `gone_directories()`) from `category_directory`,
+/// `host_category_dir`, `directory`, `repository' and `file_detail` (and `file_detail_digest`
+/// if `digest_table` is set). With `soft_delete_repositories` the repositories of the directory
+/// are disabled and detached from it instead of being deleted.
+fn cleanup_database(
+    c: &mut PgConnection,
+    gone: &[&db::models::Directory],
+    repos: &[db::models::Repository],
+    digest_table: bool,
+    soft_delete_repositories: bool,
+) -> Result<usize, diesel::result::Error> {
+    for d in gone {
+        report::removed_directory(&d.name);
+        // Delete from CategoryDirectory (Is it possible to delete multiple entries at once???)
+        // Something like 'DELETE FROM category_directory where directory_id = 10 or directory_id = 20'.
+        let delete_cd = diesel::delete(
+            db::schema::category_directory::dsl::category_directory
+                .filter(db::schema::category_directory::dsl::directory_id.eq(d.id)),
+        );
+        let debug_cd = diesel::debug_query::<diesel::pg::Pg, _>(&delete_cd);
+        debug!("{}", debug_cd);
+        if !dry_run::record("DELETE", "category_directory", debug_cd.to_string()) {
             delete_cd.execute(c)?;
+        }
 
-            // Delete from HostCategoryDir
-            let delete_host_category_dir = diesel::delete(
-                db::schema::host_category_dir::dsl::host_category_dir
-                    .filter(db::schema::host_category_dir::dsl::directory_id.eq(d.id)),
-            );
-            let debug_host_category_dir =
-                diesel::debug_query::<diesel::pg::Pg, _>(&delete_host_category_dir);
-            debug::print_step(debug_host_category_dir.to_string());
+        // Delete from HostCategoryDir
+        let delete_host_category_dir = diesel::delete(
+            db::schema::host_category_dir::dsl::host_category_dir
+                .filter(db::schema::host_category_dir::dsl::directory_id.eq(d.id)),
+        );
+        let debug_host_category_dir =
+            diesel::debug_query::<diesel::pg::Pg, _>(&delete_host_category_dir);
+        debug!("{}", debug_host_category_dir);
+        if !dry_run::record(
+            "DELETE",
+            "host_category_dir",
+            debug_host_category_dir.to_string(),
+        ) {
             delete_host_category_dir.execute(c)?;
+        }
 
-            // Delete from Repository
+        // Delete from Repository or keep the repositories
+        // for when the directory shows up elsewhere
+        if soft_delete_repositories {
+            for r in repos.iter().filter(|r| r.directory_id == Some(d.id)) {
+                report::disabled_repository(r.prefix.as_deref().unwrap_or(&r.name));
+            }
+            db::functions::detach_repositories(c, d.id)?;
+        } else {
             let delete_repository = diesel::delete(
                 db::schema::repository::dsl::repository
                     .filter(db::schema::repository::dsl::directory_id.eq(d.id)),
             );
             let debug_repository = diesel::debug_query::<diesel::pg::Pg, _>(&delete_repository);
-            debug::print_step(debug_repository.to_string());
-            delete_repository.execute(c)?;
+            debug!("{}", debug_repository);
+            if !dry_run::record("DELETE", "repository", debug_repository.to_string()) {
+                delete_repository.execute(c)?;
+            }
+        }
 
-            // And remove if from FileDetail
-            let delete_fd = diesel::delete(
-                db::schema::file_detail::dsl::file_detail
-                    .filter(db::schema::file_detail::dsl::directory_id.eq(d.id)),
-            );
-            let debug_fd = diesel::debug_query::<diesel::pg::Pg, _>(&delete_fd);
-            debug::print_step(debug_fd.to_string());
+        // And remove if from FileDetail
+        if digest_table {
+            db::functions::delete_directory_file_detail_digests(c, d.id)?;
+        }
+        let delete_fd = diesel::delete(
+            db::schema::file_detail::dsl::file_detail
+                .filter(db::schema::file_detail::dsl::directory_id.eq(d.id)),
+        );
+        let debug_fd = diesel::debug_query::<diesel::pg::Pg, _>(&delete_fd);
+        debug!("{}", debug_fd);
+        if !dry_run::record("DELETE", "file_detail", debug_fd.to_string()) {
             delete_fd.execute(c)?;
+        }
 
-            // Delete from Directory
-            let delete_dir = diesel::delete(
-                db::schema::directory::dsl::directory
-                    .filter(db::schema::directory::dsl::id.eq(d.id)),
-            );
-            let debug_dir = diesel::debug_query::<diesel::pg::Pg, _>(&delete_dir);
-            debug::print_step(debug_dir.to_string());
+        // Delete from Directory
+        let delete_dir = diesel::delete(
+            db::schema::directory::dsl::directory.filter(db::schema::directory::dsl::id.eq(d.id)),
+        );
+        let debug_dir = diesel::debug_query::<diesel::pg::Pg, _>(&delete_dir);
+        debug!("{}", debug_dir);
+        if !dry_run::record("DELETE", "directory", debug_dir.to_string()) {
             delete_dir.execute(c)?;
         }
     }
     Ok(0)
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 struct DetailsResult {
     md5_sum: String,
     sha1_sum: String,
@@ -500,183 +703,266 @@ struct DetailsResult {
     length: i64,
     timestamp: i64,
     target: String,
+    /// Additional digests (`extra_digests`) by algorithm
+    #[serde(default)]
+    extra_sums: BTreeMap<String, String>,
 }
 
-fn get_file_content(
-    base: &Option<String>,
-    topdir: &str,
-    dir: &str,
+/// Compute the checksums of `target` from its content in `reader`.
+///
+/// Returns the details and, for XML files, the content of the file.
+/// The `extra` digests are computed in addition to the default ones.
+fn hash_file(
+    reader: &mut dyn Read,
     target: &str,
-    backend: &str,
-) -> Result<(String, i64), Box<dyn Error>> {
-    use std::fs;
-
-    let backend_config_name = match backend {
-        "directory" => "local_prefix",
-        "rsync" => "checksum_base",
-        _ => return Err(format!("Do not know how to handle backend '{}'", backend).into()),
+    extra: &[digests::Extra],
+) -> Result<(DetailsResult, Vec<u8>), Box<dyn Error>> {
+    // Only XML files are kept in memory to read the timestamp from.
+    // Everything else can be a large binary file.
+    let mut body: Vec<u8> = Vec::new();
+    let keep_body = target.ends_with(".xml");
+    let digests = digests::from_reader(reader, keep_body.then_some(&mut body), extra)?;
+
+    let dr = DetailsResult {
+        md5_sum: digests.md5,
+        sha1_sum: digests.sha1,
+        sha256_sum: digests.sha256,
+        sha512_sum: digests.sha512,
+        length: digests.length,
+        timestamp: match keep_body {
+            true => xml::get_timestamp(String::from_utf8_lossy(&body).to_string()),
+            false => 0,
+        },
+        target: target.to_string(),
+        extra_sums: digests.extra,
     };
 
-    let full_target = format!(
-        "{}{}{}/{}",
-        match base {
-            Some(c) => c,
-            _ =>
-                return Err(format!(
-                    "For backend '{}' '{}' needs to be set",
-                    backend, backend_config_name
-                )
-                .into()),
-        },
-        topdir,
-        dir,
-        target,
-    );
+    Ok((dr, body))
+}
 
-    debug::STEPS.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
-    debug::print_step(format!("Reading {} for checksum creation", full_target));
+fn get_details(
+    reader: &mut dyn Read,
+    target: &str,
+    extra: &[digests::Extra],
+) -> Result<Vec<DetailsResult>, Box<dyn Error>> {
+    Ok(vec![hash_file(reader, target, extra)?.0])
+}
 
-    if backend == "directory" {
-        return Ok((
-            fs::read_to_string(&full_target)?,
-            fs::metadata(full_target)?.len() as i64,
-        ));
+/// Get the details of `repomd.xml` and of all files it references.
+///
+/// The details of the referenced files (`primary.xml.*`, `filelists.xml.*`,
+/// `updateinfo.xml.*`, ...) are taken from `repomd.xml`. Only the
+/// checksum type used in `repomd.xml` is set. Files outside of the
+/// `repodata` directory are ignored. The `extra` digests are only
+/// computed for `repomd.xml` itself.
+fn get_details_via_repomd(
+    reader: &mut dyn Read,
+    extra: &[digests::Extra],
+) -> Result<Vec<DetailsResult>, Box<dyn Error>> {
+    let (dr, body) = hash_file(reader, "repomd.xml", extra)?;
+    let repomd = xml::parse_repomd(&body)?;
+
+    let mut drs = vec![dr];
+
+    for d in repomd.data {
+        let target = match d.location.as_ref().map(|l| l.href.as_str()) {
+            Some(href) => match href.strip_prefix("repodata/") {
+                Some(t) if !t.is_empty() && !t.contains('/') => t.to_string(),
+                _ => {
+                    debug!("Ignoring {} referenced outside of repodata", href);
+                    continue;
+                }
+            },
+            _ => continue,
+        };
+        let (checksum_type, checksum) = match &d.checksum {
+            Some(c) => (c.r#type.as_str(), c.value.trim().to_string()),
+            _ => ("", String::new()),
+        };
+        let with_type = |t: &[&str]| match t.contains(&checksum_type) {
+            true => checksum.clone(),
+            false => String::new(),
+        };
+
+        drs.push(DetailsResult {
+            md5_sum: with_type(&["md5"]),
+            sha1_sum: with_type(&["sha", "sha1"]),
+            sha256_sum: with_type(&["sha256"]),
+            sha512_sum: with_type(&["sha512"]),
+            length: d.size.unwrap_or_default(),
+            timestamp: d.timestamp().unwrap_or_default(),
+            target,
+            extra_sums: BTreeMap::new(),
+        });
     }
 
-    let resp = match reqwest::blocking::get(&full_target) {
-        Ok(r) => r,
-        Err(e) => return Err(format!("Error '{}' retrieving '{}'", e, full_target).into()),
-    };
+    Ok(drs)
+}
 
-    {
-        use reqwest::StatusCode;
+/// Parse the checksums of all files listed in the checksum file `file`
+/// from its content in `reader`.
+///
+/// The checksum file can be in BSD or GNU coreutils format. The size
+/// and timestamp of the returned details are not set.
+///
+/// If a `keyring` is given the checksum file has to be clear-signed by
+/// one of its keys. Only the checksums covered by the signature are used.
+fn get_details_via_checksum_file(
+    reader: &mut dyn Read,
+    file: &str,
+    keyring: Option<&signature::Keyring>,
+) -> Result<(Vec<DetailsResult>, Option<String>), Box<dyn Error>> {
+    let mut body = String::new();
+    reader.read_to_string(&mut body)?;
+
+    let (body, signer) = match keyring {
+        Some(k) => {
+            let (signed, fingerprint) = signature::verify_cleartext(&body, k)
+                .map_err(|e| format!("Signature verification failed: {}", e))?;
+            debug!("{} signed by {}", file, fingerprint);
+            report::signature(file, &fingerprint);
+            (signed, Some(fingerprint))
+        }
+        None => (body, None),
+    };
 
-        if resp.status() != StatusCode::OK {
-            return Err(format!(
-                "Downloading '{}' returned status '{}'",
-                full_target,
-                resp.status()
-            )
-            .into());
+    let mut drs: Vec<DetailsResult> = Vec::new();
+
+    for entry in checksum::parse(&body) {
+        // A file can be listed with more than one algorithm
+        let i = match drs.iter().position(|dr| dr.target == entry.name) {
+            Some(i) => i,
+            None => {
+                drs.push(DetailsResult {
+                    md5_sum: String::new(),
+                    sha1_sum: String::new(),
+                    sha256_sum: String::new(),
+                    sha512_sum: String::new(),
+                    length: 0,
+                    timestamp: 0,
+                    target: entry.name,
+                    extra_sums: BTreeMap::new(),
+                });
+                drs.len() - 1
+            }
+        };
+        let sum = match entry.algorithm {
+            checksum::Algorithm::Md5 => &mut drs[i].md5_sum,
+            checksum::Algorithm::Sha1 => &mut drs[i].sha1_sum,
+            checksum::Algorithm::Sha256 => &mut drs[i].sha256_sum,
+            checksum::Algorithm::Sha512 => &mut drs[i].sha512_sum,
         };
+        *sum = entry.hash;
     }
-    let content_length: i64 = match resp.headers().get("content-length") {
-        Some(r) => r.to_str()?.parse::<i64>()?,
-        _ => 0,
-    };
 
-    Ok((resp.text()?, content_length))
+    Ok((drs, signer))
 }
 
-fn get_details(
-    checksum_base: &Option<String>,
-    topdir: &str,
-    dir: &str,
-    target: &str,
-    backend: &str,
-) -> Result<Vec<DetailsResult>, Box<dyn Error>> {
-    let (body, content_length) = get_file_content(checksum_base, topdir, dir, target, backend)?;
-
-    use md5::{Digest, Md5};
-    use sha1::Sha1;
-    use sha2::{Sha256, Sha512};
-
-    let mut md5 = Md5::new();
-    md5.update(&body);
-    let mut sha1 = Sha1::new();
-    sha1.update(&body);
-    let mut sha256 = Sha256::new();
-    sha256.update(&body);
-    let mut sha512 = Sha512::new();
-    sha512.update(&body);
-
-    let to_hex = |hash: &[u8]| -> String { hash.iter().map(|b| format!("{:02x}", b)).collect() };
-
-    Ok(vec![DetailsResult {
-        md5_sum: to_hex(&md5.finalize()),
-        sha1_sum: to_hex(&sha1.finalize()),
-        sha256_sum: to_hex(&sha256.finalize()),
-        sha512_sum: to_hex(&sha512.finalize()),
-        length: content_length,
-        timestamp: xml::get_timestamp(body),
-        target: target.to_string(),
-    }])
+/// Only keep the details of checksum file entries which exist in `files`.
+///
+/// Size and timestamp are taken from the scanned file.
+fn existing_files(drs: Vec<DetailsResult>, files: &[File]) -> Vec<DetailsResult> {
+    drs.into_iter()
+        .filter_map(|dr| {
+            let file = files.iter().find(|f| f.name == dr.target)?;
+            Some(DetailsResult {
+                length: file.size,
+                timestamp: file.timestamp,
+                ..dr
+            })
+        })
+        .collect()
 }
 
-fn get_details_via_checksum_file(
-    checksum_base: &Option<String>,
+/// Drop the details which do not match the content of the local files.
+fn verify_details(
+    backend: &dyn ScanBackend,
     topdir: &str,
     dir: &str,
-    target: &str,
-    backend: &str,
-    files: &Option<Vec<File>>,
+    drs: Vec<DetailsResult>,
+    verifier: &verify::Verifier,
 ) -> Result<Vec<DetailsResult>, Box<dyn Error>> {
-    let (body, _) = get_file_content(checksum_base, topdir, dir, target, backend)?;
-
-    // Pre-allocate drs based on the number of files
-    let estimated_capacity = files.as_ref().map_or(0, |f| f.len());
-    let mut drs: Vec<DetailsResult> = Vec::with_capacity(estimated_capacity);
-
-    if files.is_none() {
-        return Ok(Vec::new());
-    }
-
-    'outer: for line in body.lines() {
-        let elements = line.split(' ').collect::<Vec<_>>();
-        if elements.len() == 4 {
-            // We are looking for lines like this
-            // SHA256 (Fedora-Cloud-Base-34_Beta-1.3.x86_64.qcow2) = e2f87e760162a596a0adeb14d4d563fcad25b5954d3064d98eefda24691574b5
-            // Currently only SHA256 is used in the files so let's look for that and SHA512
-            if elements[0] != "SHA256" && elements[0] != "SHA512" {
-                continue 'outer;
+    let mut files: Vec<(std::path::PathBuf, digests::Digests)> = Vec::with_capacity(drs.len());
+    for dr in &drs {
+        let path = match backend.local_path(topdir, dir, &dr.target) {
+            Some(p) => p,
+            None => {
+                return Err(format!(
+                    "Verifying checksums is not possible with backend '{}'",
+                    backend.name()
+                )
+                .into())
             }
+        };
+        files.push((
+            path,
+            digests::Digests {
+                md5: dr.md5_sum.clone(),
+                sha1: dr.sha1_sum.clone(),
+                sha256: dr.sha256_sum.clone(),
+                sha512: dr.sha512_sum.clone(),
+                length: dr.length,
+                ..Default::default()
+            },
+        ));
+    }
 
-            // Check if the file exists
-            let mut file = File::default();
-            let file_name = elements[1].trim_start_matches('(').trim_end_matches(')');
-            for f in files.as_ref().unwrap() {
-                if f.name == file_name {
-                    file = f.clone();
-                    break;
-                }
+    let results = verifier.verify(&files);
+    let mut verified: Vec<DetailsResult> = Vec::with_capacity(drs.len());
+    for (dr, result) in drs.into_iter().zip(results) {
+        let file = format!("{}{}/{}", topdir, dir, dr.target);
+        match result {
+            verify::Verification::Match => verified.push(dr),
+            verify::Verification::TooLarge => {
+                debug!("Not verifying {}. It is too large.", file);
+                verified.push(dr);
             }
-            if file.name.is_empty() {
-                continue 'outer;
+            verify::Verification::Mismatch(reason) => {
+                warn!("Not recording {}: {}", file, reason);
+                report::checksum_mismatch(&file, reason);
             }
+        }
+    }
 
-            let dr = DetailsResult {
-                md5_sum: String::new(),
-                sha1_sum: String::new(),
-                sha256_sum: match elements[0] {
-                    "SHA256" => elements[3].to_string(),
-                    _ => String::new(),
-                },
-                sha512_sum: match elements[0] {
-                    "SHA512" => elements[3].to_string(),
-                    _ => String::new(),
-                },
-                length: file.size,
-                timestamp: file.timestamp,
-                target: file.name,
-            };
-            drs.push(dr);
+    Ok(verified)
+}
+
+/// Check that all files referenced by `repomd.xml` exist.
+///
+/// `drs` are the details returned by `get_details_via_repomd()` and
+/// `files` are the files found by the scan in the repodata directory.
+/// Returns a description of all missing files and size mismatches.
+fn check_repodata(drs: &[DetailsResult], files: &[File]) -> Result<(), String> {
+    let mut problems: Vec<String> = Vec::new();
+
+    for dr in drs.iter().filter(|dr| dr.target != "repomd.xml") {
+        match files.iter().find(|f| f.name == dr.target) {
+            None => problems.push(format!("{} is missing", dr.target)),
+            Some(f) if dr.length > 0 && f.size != dr.length => problems.push(format!(
+                "{} has size {} instead of {}",
+                dr.target, f.size, dr.length
+            )),
+            _ => (),
         }
     }
 
-    Ok(drs)
+    match problems.is_empty() {
+        true => Ok(()),
+        false => Err(problems.join(", ")),
+    }
 }
 
 /// Parameter for the `fill_ifds()` funcion
 struct FillIfds<'a> {
     /// InsertFileDetail return vector
     ifds: &'a mut Vec<db::models::InsertFileDetail>,
+    /// IDs of entries in `fds` of a repodata directory which are
+    /// no longer referenced by 'repomd.xml'
+    unreferenced: &'a mut Vec<i32>,
     /// The name of the checksum target file
     target: &'a str,
-    /// Which backend this is using
-    backend: &'a str,
-    /// The base for creating checksums. For rsync a http URL
-    /// For file a directory path
-    checksum_base: &'a Option<String>,
+    /// The backend used to retrieve the checksum target
+    backend: &'a dyn ScanBackend,
     /// topdir as defined by the category
     topdir: &'a str,
     /// The current directory this is about
@@ -685,34 +971,162 @@ struct FillIfds<'a> {
     d_id: i32,
     /// The currently in the database existing entries
     fds: &'a [db::models::FileDetail],
-    /// The list of files in this directory. Only used
-    /// for '-CHECKSUM' files.
+    /// The list of files in this directory. Used for checksum
+    /// files and to check the files referenced by 'repomd.xml'.
     files: &'a Option<Vec<File>>,
+    /// Also create entries for the files referenced by 'repomd.xml'
+    repodata_details: bool,
+    /// Keys trusted to sign checksum files
+    keyring: Option<&'a signature::Keyring>,
+    /// Verify checksum files against the local files
+    verifier: Option<&'a verify::Verifier<'a>>,
+    /// Digests computed in addition to the `file_detail` columns
+    extra_digests: &'a [digests::Extra],
+    /// File name patterns of checksum files
+    checksum_files: &'a [glob::Pattern],
+    /// Details of earlier downloads of `target`
+    details_cache: &'a details_cache::Cache,
+    /// The details of `target` if they have already been retrieved
+    details: Option<Fetched>,
 }
 
-fn fill_ifds(p: &mut FillIfds) -> Result<(), Box<dyn Error>> {
-    let drs_result = match p.backend {
-        "rsync" | "directory" if p.target.ends_with("-CHECKSUM") => get_details_via_checksum_file(
-            p.checksum_base,
-            p.topdir,
-            p.dir,
-            p.target,
-            p.backend,
-            p.files,
-        ),
-        "rsync" | "directory" => get_details(p.checksum_base, p.topdir, p.dir, p.target, p.backend),
-        _ => return Err(format!("Unsupported scan backend '{}'", p.backend).into()),
+/// The details of a file retrieved by `fetch_file_details()`.
+type Fetched = Result<Vec<DetailsResult>, String>;
+
+/// Retrieve `p.target` and compute its details.
+///
+/// Downloaded files are cached by URL. A file the server reports as
+/// unchanged since the cached download is neither downloaded nor
+/// parsed or hashed again. With a keyring only checksum files
+/// signed by a key of the keyring are taken from the cache.
+fn cached_details(p: &FillIfds) -> Result<Vec<DetailsResult>, Box<dyn Error>> {
+    let file = format!("{}{}/{}", p.topdir, p.dir, p.target);
+    let checksum_file = checksum::is_checksum_file(p.target, p.checksum_files);
+    let url = p.backend.url(p.topdir, p.dir, p.target);
+    let cached = url
+        .as_ref()
+        .and_then(|u| p.details_cache.get(u, p.extra_digests))
+        .filter(|c| match p.keyring {
+            Some(k) if checksum_file => c.signer.as_deref().is_some_and(|s| k.contains(s)),
+            _ => true,
+        });
+
+    let opened = p.backend.open_file_if_modified(
+        p.topdir,
+        p.dir,
+        p.target,
+        cached.as_ref().map(|c| &c.validators),
+    )?;
+    let (mut reader, validators) = match (opened, cached) {
+        (Some(o), _) => o,
+        (None, Some(c)) => {
+            debug!("{} is unchanged", file);
+            if let (Some(_), Some(signer)) = (p.keyring, &c.signer) {
+                report::signature(&file, signer);
+            }
+            return Ok(c.details);
+        }
+        (None, None) => return Err("Not modified without a cached download".into()),
+    };
+
+    let (drs, signer) = if p.target == "repomd.xml" {
+        (get_details_via_repomd(&mut reader, p.extra_digests)?, None)
+    } else if checksum_file {
+        get_details_via_checksum_file(&mut reader, &file, p.keyring)?
+    } else {
+        (get_details(&mut reader, p.target, p.extra_digests)?, None)
+    };
+
+    if let Some(url) = url {
+        p.details_cache
+            .insert(&url, validators, p.extra_digests, signer, &drs);
+    }
+
+    Ok(drs)
+}
+
+/// Get the details of `p.target`.
+///
+/// For checksum files only the entries of files in this directory
+/// are returned. With a verifier the entries which do not match the
+/// content of the local files are dropped.
+fn get_file_details(p: &FillIfds) -> Result<Vec<DetailsResult>, Box<dyn Error>> {
+    let drs = cached_details(p)?;
+    if p.target == "repomd.xml" || !checksum::is_checksum_file(p.target, p.checksum_files) {
+        return Ok(drs);
+    }
+
+    let drs = match p.files {
+        Some(files) => existing_files(drs, files),
+        None => Vec::new(),
+    };
+
+    match p.verifier {
+        Some(v) => verify_details(p.backend, p.topdir, p.dir, drs, v),
+        None => Ok(drs),
+    }
+}
+
+/// Add the details of `target` to `ifds` if they are not yet in the database.
+///
+/// Returns `false` if the details of a 'repomd.xml' have not been
+/// added because files it references are missing or incomplete.
+fn fill_ifds(p: &mut FillIfds) -> Result<bool, Box<dyn Error>> {
+    let drs_result = match p.details.take() {
+        Some(fetched) => fetched.map_err(|e| e.into()),
+        None => get_file_details(p),
     };
 
     let drs = match drs_result {
         Ok(d) => d,
         Err(e) => {
-            println!(
+            warn!(
                 "Getting file details for {} via {} failed: {}. Skipping.",
-                p.target, p.backend, e
+                p.target,
+                p.backend.name(),
+                e
             );
-            return Ok(());
+            report::checksum_failure(format!(
+                "Getting file details for {}{}/{} failed: {}",
+                p.topdir, p.dir, p.target, e
+            ));
+            return Ok(true);
+        }
+    };
+
+    let drs = match p.target == "repomd.xml" {
+        true => {
+            if let Some(files) = p.files {
+                if let Err(e) = check_repodata(&drs, files) {
+                    warn!(
+                        "Not recording {}{}/repomd.xml. Inconsistent repodata: {}",
+                        p.topdir, p.dir, e
+                    );
+                    report::skipped_repodata(
+                        &format!("{}{}", p.topdir, p.dir),
+                        format!("Inconsistent repodata: {}", e),
+                    );
+                    return Ok(false);
+                }
+            }
+            // Files of older repodata have hash-prefixed names and
+            // would never be aged as each name is only used once
+            let referenced: HashSet<&str> = drs.iter().map(|dr| dr.target.as_str()).collect();
+            let d_id = p.d_id;
+            p.unreferenced.extend(
+                p.fds
+                    .iter()
+                    .filter(|fd| {
+                        fd.directory_id == d_id && !referenced.contains(fd.filename.as_str())
+                    })
+                    .map(|fd| fd.id),
+            );
+            match p.repodata_details {
+                true => drs,
+                false => drs.into_iter().take(1).collect(),
+            }
         }
+        false => drs,
     };
 
     for dr in drs {
@@ -746,6 +1160,7 @@ fn fill_ifds(p: &mut FillIfds) -> Result<(), Box<dyn Error>> {
         }
 
         if !found_in_db {
+            report::inserted_file_detail(&format!("{}{}", p.topdir, p.dir), &dr.target);
             p.ifds.push(db::models::InsertFileDetail {
                 directory_id: p.d_id,
                 filename: dr.target.to_string(),
@@ -755,11 +1170,12 @@ fn fill_ifds(p: &mut FillIfds) -> Result<(), Box<dyn Error>> {
                 md5: Some(dr.md5_sum),
                 sha256: Some(dr.sha256_sum),
                 sha512: Some(dr.sha512_sum),
+                digests: dr.extra_sums,
             });
         }
     }
 
-    Ok(())
+    Ok(true)
 }
 
 /// Parameter for the `find_repositories()` function.
@@ -768,11 +1184,9 @@ struct FindRepositories<'a> {
     c: &'a mut PgConnection,
     /// The hashmap of the file system scan
     cds: &'a mut HashMap<String, CategoryDirectory>,
-    /// For rsync based scans a HTTP(S) URL where files can
-    /// be downloaded from for checksum creation.
-    /// For file based scans the path on the local file system
-    /// which contains all the files.
-    checksum_base: Option<String>,
+    /// The backend used to scan the primary mirror. It is also
+    /// used to retrieve files for checksum creation.
+    backend: &'a dyn ScanBackend,
     top: String,
     /// The category all these files belong to
     cat: &'a db::functions::Category,
@@ -785,6 +1199,11 @@ struct FindRepositories<'a> {
     /// The content from the table `file_detail` which will be
     /// amended if a new repomd.xml file has been found.
     fds: &'a mut Vec<db::models::FileDetail>,
+    /// The architectures from the database
+    arches: &'a [db::models::Arch],
+    /// The versions from the database. Newly created versions
+    /// are added.
+    versions: &'a mut Vec<db::models::Version>,
     /// List of directory prefixes which should be ignored
     /// when trying to find repositories.
     skip_paths: &'a [String],
@@ -797,11 +1216,129 @@ struct FindRepositories<'a> {
     /// If one of the following strings is part of the path
     /// a newly created version will be set to display = false
     do_not_display_paths: &'a [String],
-    /// Which backend is used to scan the primary mirror
-    backend: String,
     /// List of Repository aliases for some repositories not
     /// following the default naming scheme.
     aliases: &'a [settings::RepositoryAlias],
+    /// Create `file_detail` entries for all files referenced by `repomd.xml`
+    repodata_details: bool,
+    /// If set only signed checksum files are used
+    keyring: Option<&'a signature::Keyring>,
+    /// If set checksum files are verified against the local files
+    verifier: Option<&'a verify::Verifier<'a>>,
+    /// Digests stored in `file_detail_digest` for new entries
+    extra_digests: &'a [digests::Extra],
+    /// File name patterns of checksum files
+    checksum_files: &'a [glob::Pattern],
+    /// Details of earlier downloads of checksum files and `repomd.xml`
+    details_cache: &'a details_cache::Cache,
+    /// The details of checksum files and `repomd.xml` retrieved by
+    /// `fetch_file_details()` before the transaction of the category
+    fetched: HashMap<(String, String), Fetched>,
+    /// Also delete the rows in `file_detail_digest` of deleted entries
+    digest_table: bool,
+}
+
+/// Parameter for the `fetch_file_details()` function.
+struct FetchFileDetails<'a> {
+    /// The hashmap of the file system scan
+    cds: &'a HashMap<String, CategoryDirectory>,
+    /// The backend used to retrieve the files
+    backend: &'a dyn ScanBackend,
+    top: &'a str,
+    /// If set only signed checksum files are used
+    keyring: Option<&'a signature::Keyring>,
+    /// If set checksum files are verified against the local files
+    verifier: Option<&'a verify::Verifier<'a>>,
+    /// Digests computed in addition to the `file_detail` columns
+    extra_digests: &'a [digests::Extra],
+    /// File name patterns of checksum files
+    checksum_files: &'a [glob::Pattern],
+    /// Details of earlier downloads of checksum files and `repomd.xml`
+    details_cache: &'a details_cache::Cache,
+    /// Number of files retrieved in parallel for checksum creation
+    fetch_jobs: usize,
+}
+
+/// Retrieve the details of the checksum files and `repomd.xml`
+/// of all changed directories.
+///
+/// Up to `p.fetch_jobs` files are retrieved in parallel. Nothing is
+/// written to the database. Returns the details by directory and file.
+fn fetch_file_details(p: &FetchFileDetails) -> HashMap<(String, String), Fetched> {
+    let mut jobs: Vec<(&str, String)> = Vec::new();
+    for (k, cd) in p.cds.iter().filter(|(_, cd)| cd.ctime_changed) {
+        for f in &cd.files {
+            if checksum::is_checksum_file(&f.name, p.checksum_files) {
+                jobs.push((k, f.name.clone()));
+            }
+        }
+        if basename(k.to_string()) == *"repodata" {
+            jobs.push((k, String::from("repomd.xml")));
+        }
+    }
+
+    // The database connection cannot be shared with the threads
+    let (cds, backend, top, keyring, verifier, extra_digests, checksum_files, details_cache) = (
+        p.cds,
+        p.backend,
+        p.top,
+        p.keyring,
+        p.verifier,
+        p.extra_digests,
+        p.checksum_files,
+        p.details_cache,
+    );
+    let category = logging::category();
+    let next = AtomicUsize::new(0);
+    let results: Mutex<HashMap<(String, String), Fetched>> = Mutex::new(HashMap::new());
+
+    let worker = || {
+        logging::set_category(category.as_deref());
+        loop {
+            let i = next.fetch_add(1, Ordering::SeqCst);
+            let (dir, target) = match jobs.get(i) {
+                Some(j) => j,
+                _ => break,
+            };
+            let repomd = target == "repomd.xml";
+            let fetched = get_file_details(&FillIfds {
+                ifds: &mut Vec::new(),
+                unreferenced: &mut Vec::new(),
+                target,
+                backend,
+                topdir: top,
+                dir,
+                d_id: cds[*dir].directory_id,
+                fds: &[],
+                files: &Some(cds[*dir].files.clone()),
+                repodata_details: false,
+                keyring: if repomd { None } else { keyring },
+                verifier: if repomd { None } else { verifier },
+                extra_digests,
+                checksum_files: if repomd { &[] } else { checksum_files },
+                details_cache,
+                details: None,
+            })
+            .map_err(|e| e.to_string());
+            results
+                .lock()
+                .unwrap()
+                .insert((dir.to_string(), target.clone()), fetched);
+        }
+        // Signatures and checksum mismatches recorded on this thread
+        report::take("", &Ok(()))
+    };
+
+    std::thread::scope(|s| {
+        let workers: Vec<_> = (0..p.fetch_jobs.clamp(1, jobs.len().max(1)))
+            .map(|_| s.spawn(worker))
+            .collect();
+        for w in workers {
+            report::merge(w.join().unwrap());
+        }
+    });
+
+    results.into_inner().unwrap()
 }
 
 /// Find repositories in the list of scanned directories.
@@ -809,59 +1346,82 @@ struct FindRepositories<'a> {
 /// Based on the input structure `FindRepositories` this
 /// function will create new repository objects in the database.
 fn find_repositories(p: &mut FindRepositories) -> Result<usize, Box<dyn Error>> {
-    if p.backend != "rsync" && p.backend != "directory" {
-        return Err(format!("Cannot handle backend type {}", p.backend).into());
-    }
-
     // Pre-allocate ifds with estimated capacity
-    // Estimate: ~10% of directories might have CHECKSUM files with ~5 checksums each
+    // Estimate: ~10% of directories might have checksum files with ~5 checksums each
     let estimated_capacity = (p.cds.len() / 10).max(1) * 5;
     let mut ifds: Vec<db::models::InsertFileDetail> = Vec::with_capacity(estimated_capacity);
+    let mut unreferenced: Vec<i32> = Vec::new();
 
-    let arches = db::functions::get_arches(p.c)?;
-    let mut versions = db::functions::get_versions(p.c)?;
     let fds = p.fds.clone();
 
+    let mut fetched = std::mem::take(&mut p.fetched);
+
+    // A detached repository of this product might have moved
+    // to one of the repodata directories which did not change.
+    let detached = detached_repos(p.repos, p.versions, p.cat.product_id);
+
     let list: Vec<String> = p.cds.keys().cloned().collect();
     'outer: for k in list {
-        // No need to look at unchanged entries
-        if !p.cds.get(&k).unwrap().ctime_changed {
+        let changed = p.cds.get(&k).unwrap().ctime_changed;
+        let repodata = basename(k.to_string()) == *"repodata";
+        // No need to look at unchanged entries unless they might
+        // be the new location of a detached repository
+        if !(changed || !detached.is_empty() && repodata) {
             continue;
         }
         // Let's go over the files in this directory to see if there
-        // is a file ending with '-CHECKSUM' which might contain
-        // checksums.
+        // is a checksum file ('*-CHECKSUM', 'SHA256SUMS', ...).
         for f in &p.cds.get(&k).unwrap().files {
-            // Currently only files ending in '-CHECKSUM' exist
-            if f.name.ends_with("-CHECKSUM") {
+            if changed && checksum::is_checksum_file(&f.name, p.checksum_files) {
                 // We found such a file. Now let's parse it and add it
                 // to 'ifds' to be later added to the database.
-                println!("Found CHECKSUM {}", f.name);
+                info!("Found checksum file {}", f.name);
                 fill_ifds(&mut FillIfds {
                     ifds: &mut ifds,
+                    unreferenced: &mut unreferenced,
                     target: &f.name,
-                    backend: &p.backend,
-                    checksum_base: &p.checksum_base,
+                    backend: p.backend,
                     topdir: &p.top,
                     dir: &k,
                     d_id: p.cds[&k].directory_id,
                     fds: &fds,
                     files: &Some(p.cds.get(&k).unwrap().files.clone()),
+                    repodata_details: false,
+                    keyring: p.keyring,
+                    verifier: p.verifier,
+                    extra_digests: p.extra_digests,
+                    checksum_files: p.checksum_files,
+                    details_cache: p.details_cache,
+                    details: fetched.remove(&(k.clone(), f.name.clone())),
                 })?;
             }
         }
-        if basename(k.to_string()) == *"repodata" {
-            fill_ifds(&mut FillIfds {
-                ifds: &mut ifds,
-                target: "repomd.xml",
-                backend: &p.backend,
-                checksum_base: &p.checksum_base,
-                topdir: &p.top,
-                dir: &k,
-                d_id: p.cds[&k].directory_id,
-                fds: &fds,
-                files: &None,
-            })?;
+        if repodata {
+            let consistent = !changed
+                || fill_ifds(&mut FillIfds {
+                    ifds: &mut ifds,
+                    unreferenced: &mut unreferenced,
+                    target: "repomd.xml",
+                    backend: p.backend,
+                    topdir: &p.top,
+                    dir: &k,
+                    d_id: p.cds[&k].directory_id,
+                    fds: &fds,
+                    files: &Some(p.cds.get(&k).unwrap().files.clone()),
+                    repodata_details: p.repodata_details,
+                    keyring: None,
+                    verifier: None,
+                    extra_digests: p.extra_digests,
+                    checksum_files: &[],
+                    details_cache: p.details_cache,
+                    details: fetched.remove(&(k.clone(), String::from("repomd.xml"))),
+                })?;
+            if !consistent {
+                // The repodata is probably still being synced to the
+                // primary mirror. Look at it again during the next scan.
+                db::functions::reset_directory_ctime(p.c, p.cds[&k].directory_id)?;
+                continue;
+            }
 
             for s in p.skip_repository_paths {
                 if k.contains(s) {
@@ -883,57 +1443,178 @@ fn find_repositories(p: &mut FindRepositories) -> Result<usize, Box<dyn Error>>
                 }
             }
 
-            let (version_name, version_id, arch_id) = guess_ver_arch_from_path(
+            if !changed && !may_have_moved(&with_topdir, &detached, p.arches, p.versions) {
+                continue;
+            }
+
+            let (version_name, version_id, arch_id) = match guess_ver_arch_from_path(
                 p.c,
                 with_topdir.clone(),
-                &arches,
-                &mut versions,
+                p.arches,
+                p.versions,
                 p.cat.product_id,
                 p.test_paths,
                 p.do_not_display_paths,
-            )?;
+            ) {
+                Ok(g) => g,
+                Err(e) if !changed => {
+                    debug!("Not looking for a detached repository in {}: {}", k, e);
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
             if version_id == -1 {
-                println!("Not able to guess version for {}", with_topdir);
-                println!("Not creating repository in database");
+                if !changed {
+                    continue;
+                }
+                warn!(
+                    "Not able to guess version for {}. Not creating repository in database",
+                    with_topdir
+                );
+                report::skipped_repodata(
+                    &format!("{}{}", p.top, k),
+                    String::from("Not able to guess version"),
+                );
                 continue;
             }
             let prefix = repo_prefix(with_topdir.clone(), version_name, p.rms, p.aliases);
             if prefix.is_empty() {
-                println!("Not able to determine prefix for {}", with_topdir.clone());
-            }
-            if !check_for_repo(p.repos, prefix.clone(), arch_id) {
-                if let Err(e) = db::functions::create_repository(
-                    p.c,
-                    cd.directory_id,
-                    with_topdir,
-                    p.cat.id,
-                    version_id,
-                    arch_id,
-                    prefix.clone(),
-                ) {
-                    println!(
-                        "Repository creation failed for {}: {}. Skipping.",
-                        prefix, e
-                    );
+                if !changed {
                     continue;
                 }
+                warn!("Not able to determine prefix for {}", with_topdir.clone());
             }
-        }
-    }
+            // Unchanged directories are only looked at to attach
+            // a detached repository
+            if let Some(r) = detached_repo(&detached, &prefix, arch_id) {
+                let (cat, repository_id) = (p.cat, r.id);
+                match p.c.transaction(|c| {
+                    db::functions::lock_product(c, cat.product_id)?;
+                    db::functions::attach_repository(
+                        c,
+                        repository_id,
+                        cd.directory_id,
+                        with_topdir.clone(),
+                        cat.id,
+                        version_id,
+                    )
+                }) {
+                    Ok(0) => (),
+                    Ok(_) => {
+                        info!(
+                            "Attached Repository(prefix={}) to Directory {}",
+                            prefix, with_topdir
+                        );
+                        report::reenabled_repository(&prefix);
+                    }
+                    Err(e) => {
+                        error!("Attaching repository {} failed: {}. Skipping.", prefix, e);
+                        report::skipped_repodata(
+                            &format!("{}{}", p.top, k),
+                            format!("Attaching repository {} failed: {}", prefix, e),
+                        );
+                    }
+                }
+            } else if changed && !check_for_repo(p.repos, prefix.clone(), arch_id) {
+                // The savepoint keeps the surrounding transaction usable
+                // if the repository creation fails.
+                let cat = p.cat;
+                match p.c.transaction(|c| {
+                    // Another scan running in parallel might have just
+                    // created this repository.
+                    db::functions::lock_product(c, cat.product_id)?;
+                    if db::functions::repository_exists(c, &prefix, arch_id)? {
+                        return Ok(0);
+                    }
+                    db::functions::create_repository(
+                        c,
+                        cd.directory_id,
+                        with_topdir,
+                        cat.id,
+                        version_id,
+                        arch_id,
+                        prefix.clone(),
+                    )
+                }) {
+                    Ok(0) => (),
+                    Ok(_) => report::created_repository(&prefix),
+                    Err(e) => {
+                        error!(
+                            "Repository creation failed for {}: {}. Skipping.",
+                            prefix, e
+                        );
+                        report::skipped_repodata(
+                            &format!("{}{}", p.top, k),
+                            format!("Repository creation failed for {}: {}", prefix, e),
+                        );
+                        continue;
+                    }
+                }
+            }
+        }
+    }
 
     if !ifds.is_empty() {
         let insert = diesel::insert_into(db::schema::file_detail::dsl::file_detail).values(&ifds);
 
-        debug::STEPS.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
         let debug = diesel::debug_query::<diesel::pg::Pg, _>(&insert);
-        debug::print_step(debug.to_string());
+        debug!("{}", debug);
+
+        let result = match dry_run::record("INSERT", "file_detail", debug.to_string()) {
+            true => ifds
+                .iter()
+                .map(|i| db::models::FileDetail {
+                    id: dry_run::next_id(),
+                    directory_id: i.directory_id,
+                    filename: i.filename.clone(),
+                    timestamp: i.timestamp,
+                    size: i.size,
+                    sha1: i.sha1.clone(),
+                    md5: i.md5.clone(),
+                    sha256: i.sha256.clone(),
+                    sha512: i.sha512.clone(),
+                })
+                .collect(),
+            false => insert.get_results::<db::models::FileDetail>(p.c)?,
+        };
+
+        // The rows are returned in the order they have been inserted
+        let digests: Vec<db::models::InsertFileDetailDigest> = ifds
+            .iter()
+            .zip(&result)
+            .flat_map(|(i, r)| {
+                i.digests.iter().map(move |(algorithm, digest)| {
+                    db::models::InsertFileDetailDigest {
+                        file_detail_id: r.id,
+                        algorithm: algorithm.clone(),
+                        digest: digest.clone(),
+                    }
+                })
+            })
+            .collect();
+        if !digests.is_empty() {
+            db::functions::insert_file_detail_digests(p.c, &digests)?;
+        }
 
-        let result = insert.get_results::<db::models::FileDetail>(p.c)?;
         for r in result {
             p.fds.push(r.clone());
         }
     }
 
+    if !unreferenced.is_empty() {
+        info!(
+            "Deleting {} file_detail entries no longer referenced by repomd.xml",
+            unreferenced.len()
+        );
+        delete_file_details(
+            p.c,
+            &unreferenced,
+            p.digest_table,
+            report::pruned_file_detail,
+        )?;
+        p.fds.retain(|fd| !unreferenced.contains(&fd.id));
+    }
+
     Ok(0)
 }
 
@@ -942,13 +1623,15 @@ fn is_excluded(path: String, excludes: &[String]) -> bool {
         let pattern = match Regex::new(ex.as_str()) {
             Ok(p) => p,
             Err(_) => {
-                println!("Cannot handle exclude regex {}. Skipping", ex);
+                warn!("Cannot handle exclude regex {}. Skipping", ex);
                 continue;
             }
         };
 
         if pattern.is_match(&path) {
-            println!("{} is excluded because of {}", path, ex);
+            if report::excluded(&path, ex) {
+                info!("{} is excluded because of {}", path, ex);
+            }
             return true;
         }
     }
@@ -976,7 +1659,7 @@ fn add_entry_to_category_directories(
                 _ => String::from(p),
             },
             _ => {
-                println!("Failed getting parent path for {}", name);
+                warn!("Failed getting parent path for {}", name);
                 return;
             }
         },
@@ -992,6 +1675,13 @@ fn add_entry_to_category_directories(
         return;
     }
 
+    trace!(
+        "Found {} '{}' in '{}'",
+        if fi.is_directory { "directory" } else { "file" },
+        base,
+        dir
+    );
+
     let cd: &mut CategoryDirectory = match cds.get_mut(&dir) {
         Some(v) => &mut *v,
         None => {
@@ -1078,9 +1768,12 @@ fn update_category_directory(
 
     let insert = diesel::insert_into(category_directory).values(&new_cds);
 
-    debug::STEPS.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
     let debug = diesel::debug_query::<diesel::pg::Pg, _>(&insert);
-    debug::print_step(debug.to_string());
+    debug!("{}", debug);
+
+    if dry_run::record("INSERT", "category_directory", debug.to_string()) {
+        return Ok(new_cds.len());
+    }
 
     insert.execute(c)
 }
@@ -1119,16 +1812,54 @@ fn add_directories(
 
     let insert = diesel::insert_into(directory).values(&new_directories);
 
-    debug::STEPS.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
-    debug::print_step(format!("INSERT INTO directory {:?}", new_directories));
+    debug!("INSERT INTO directory {:?}", new_directories);
 
-    let result = insert.get_results::<db::models::Directory>(c)?;
+    let result = match dry_run::record(
+        "INSERT",
+        "directory",
+        format!("INSERT INTO directory {:?}", new_directories),
+    ) {
+        true => new_directories
+            .iter()
+            .map(|d| db::models::Directory {
+                id: dry_run::next_id(),
+                name: d.name.to_string(),
+                files: d.files.clone(),
+                readable: *d.readable,
+                ctime: *d.ctime,
+            })
+            .collect(),
+        false => insert.get_results::<db::models::Directory>(c)?,
+    };
 
     update_category_directory(c, &result, cat_id)?;
 
     Ok(result)
 }
 
+/// Mark the directories which are new or whose ctime differs
+/// from the directories `dirs` in the database as changed.
+///
+/// `sync_category_directories()` marks the same directories when
+/// writing them. This allows to look at the changed directories
+/// before the transaction of the category is opened.
+fn mark_changed_directories(
+    cds: &mut HashMap<String, CategoryDirectory>,
+    dirs: &[db::models::Directory],
+    topdir: &str,
+) {
+    let ctimes: HashMap<&str, i64> = dirs.iter().map(|d| (d.name.as_str(), d.ctime)).collect();
+    for (k, cd) in cds.iter_mut() {
+        let mut with_topdir = format!("{}{}", topdir, k);
+        if k.is_empty() {
+            with_topdir.pop();
+        }
+        if ctimes.get(with_topdir.as_str()) != Some(&cd.ctime) {
+            cd.ctime_changed = true;
+        }
+    }
+}
+
 fn sync_category_directories(
     c: &mut PgConnection,
     topdir: String,
@@ -1184,13 +1915,9 @@ fn sync_category_directories(
         }
     }
 
-    debug::STEPS.fetch_add(
-        update_directories.len(),
-        std::sync::atomic::Ordering::SeqCst,
-    );
-
     let new_dirs = add_directories(c, &ad, cat_id)?;
     for i in &new_dirs {
+        report::new_directory(&i.name);
         dirs.push(i.clone());
 
         cds.get_mut(i.name.trim_start_matches(&topdir))
@@ -1227,11 +1954,21 @@ fn sync_category_directories(
             ud.files = Some(&u.dir.files);
         }
 
+        report::changed_directory(&u.dir.name);
+
         let target = directory.filter(db::schema::directory::dsl::id.eq(u.dir.id));
 
         let update = diesel::update(target).set(&ud);
 
-        debug::print_step(format!("UPDATE directory {:?} where ID = {}", ud, u.dir.id));
+        debug!("UPDATE directory {:?} where ID = {}", ud, u.dir.id);
+
+        if dry_run::record(
+            "UPDATE",
+            "directory",
+            format!("UPDATE directory {:?} where ID = {}", ud, u.dir.id),
+        ) {
+            continue;
+        }
 
         update.execute(c)?;
     }
@@ -1277,6 +2014,254 @@ fn is_not_hidden(entry: &DirEntry) -> bool {
         .unwrap_or(false)
 }
 
+/// Parse the content of a `fullfiletimelist-*` file.
+///
+/// Each line consists of the tab separated fields timestamp, type,
+/// size and path. All other lines are ignored.
+fn parse_fullfiletimelist(
+    data: &[u8],
+    cds: &mut HashMap<String, CategoryDirectory>,
+    excludes: &[String],
+    topdir: &str,
+    category_name: &str,
+) -> Result<(), Box<dyn Error>> {
+    for line in data.split(|elem| elem == &b'\n') {
+        let v: Vec<&str> = std::str::from_utf8(line)?.split('\t').collect();
+        if v.len() < 4 {
+            continue;
+        }
+        // For "Fedora Linux" category, remove "linux/" prefix (first 6 chars)
+        let path = if category_name == "Fedora Linux" && v[3].len() > 6 {
+            v[3][6..].to_string()
+        } else {
+            v[3].to_string()
+        };
+        let info = FileInfo {
+            is_directory: v[1].starts_with('d'),
+            is_readable: !v[1].contains('-'),
+            size: v[2].parse()?,
+            timestamp: v[0].parse()?,
+            name: Some(path),
+        };
+        add_entry_to_category_directories(info, cds, excludes, topdir);
+    }
+
+    Ok(())
+}
+
+/// Returns the URL of the directory containing the `fullfiletimelist-*`
+/// of a remote category. For the "Fedora Linux" category it is one
+/// directory up.
+fn fullfiletimelist_base(url: &str, category_name: &str) -> String {
+    let url = url.trim_end_matches('/');
+    if category_name == "Fedora Linux" {
+        if let Some((parent, _)) = url.rsplit_once('/') {
+            return format!("{}/", parent);
+        }
+    }
+
+    format!("{}/", url)
+}
+
+/// Check if a remote `fullfiletimelist-*` is too old to be used.
+fn fullfiletimelist_is_stale(name: &str, timestamp: i64, max_age_hours: i64) -> bool {
+    let age = chrono::offset::Local::now().timestamp() - timestamp;
+    if age > max_age_hours * 60 * 60 {
+        warn!(
+            "{} is {} hours old (maximum {} hours). Ignoring it.",
+            name,
+            age / 60 / 60,
+            max_age_hours
+        );
+        return true;
+    }
+
+    false
+}
+
+/// More than one file matches `fullfiletimelist-*`.
+///
+/// Unlike a missing or stale `fullfiletimelist-*` this is a
+/// configuration error and the scan of the category fails.
+#[derive(Debug)]
+struct AmbiguousFullfiletimelist(String);
+
+impl std::fmt::Display for AmbiguousFullfiletimelist {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Error for AmbiguousFullfiletimelist {}
+
+/// Fail if more than one of the `names` matches `fullfiletimelist-*`.
+///
+/// Which of them is the right one cannot be guessed.
+fn unambiguous_fullfiletimelist(
+    mut names: Vec<String>,
+    source: &str,
+) -> Result<(), AmbiguousFullfiletimelist> {
+    if names.len() < 2 {
+        return Ok(());
+    }
+    names.sort();
+
+    Err(AmbiguousFullfiletimelist(format!(
+        "The name of the fullfiletimelist at {} is ambiguous: {}. Set 'fullfiletimelist' of the category.",
+        source,
+        names.join(", ")
+    )))
+}
+
+/// Read the `fullfiletimelist-*` rsync downloaded into `dir`.
+///
+/// Fails if rsync downloaded more than one file.
+fn read_fetched_fullfiletimelist(
+    dir: &Path,
+    source: &str,
+    max_age_hours: i64,
+) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+    let mut files: Vec<PathBuf> = Vec::new();
+    for f in std::fs::read_dir(dir)? {
+        files.push(f?.path());
+    }
+    unambiguous_fullfiletimelist(
+        files
+            .iter()
+            .map(|f| {
+                f.file_name()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .to_string()
+            })
+            .collect(),
+        source,
+    )?;
+    let file = match files.pop() {
+        Some(f) => f,
+        _ => return Ok(None),
+    };
+    let timestamp = std::fs::metadata(&file)?
+        .modified()?
+        .duration_since(SystemTime::UNIX_EPOCH)?
+        .as_secs() as i64;
+    if fullfiletimelist_is_stale(source, timestamp, max_age_hours) {
+        return Ok(None);
+    }
+
+    Ok(Some(std::fs::read(file)?))
+}
+
+/// Download the remote `fullfiletimelist-*` using rsync.
+///
+/// If `name` is not set the file matching `fullfiletimelist-*` is
+/// used. Returns `None` if no (recent) file exists and fails if more
+/// than one file matches.
+fn fetch_fullfiletimelist_with_rsync(
+    base: &str,
+    name: &Option<String>,
+    category_rsync_options: &[String],
+    rsync_options: &[String],
+    max_age_hours: i64,
+) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+    static FETCH_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+    let source = format!(
+        "{}{}",
+        base,
+        name.as_deref().unwrap_or("fullfiletimelist-*")
+    );
+    let tmp = env::temp_dir().join(format!(
+        "scan-primary-mirror-{}-{}",
+        process::id(),
+        FETCH_COUNT.fetch_add(1, Ordering::SeqCst)
+    ));
+    std::fs::create_dir_all(&tmp)?;
+
+    debug!("Running rsync -t {} {}", source, tmp.display());
+
+    let output = Command::new("rsync")
+        .arg("-t")
+        .args(rsync_options)
+        .args(category_rsync_options)
+        .arg(&source)
+        .arg(format!("{}/", tmp.display()))
+        .output();
+
+    let result = match output {
+        Ok(o) if o.status.success() => read_fetched_fullfiletimelist(&tmp, &source, max_age_hours),
+        Ok(o) => {
+            warn!(
+                "Retrieving {} failed with {}: {}",
+                source,
+                rsync::describe_exit(&o.status),
+                String::from_utf8_lossy(&o.stderr).trim()
+            );
+            Ok(None)
+        }
+        Err(e) => Err(e.into()),
+    };
+
+    std::fs::remove_dir_all(&tmp)?;
+
+    result
+}
+
+/// Download the remote `fullfiletimelist-*` via HTTP(S).
+///
+/// If `name` is not set the directory index of `base` is searched for
+/// a file matching `fullfiletimelist-*`. Returns `None` if no (recent)
+/// file exists and fails if more than one file matches.
+fn fetch_fullfiletimelist_with_http(
+    base: &str,
+    name: &Option<String>,
+    max_age_hours: i64,
+    client: &http::Client,
+) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+    let name = match name {
+        Some(n) => n.to_string(),
+        _ => {
+            let resp = client.get(base)?;
+            if !resp.status().is_success() {
+                return Ok(None);
+            }
+            let index = String::from_utf8_lossy(&http::read_body(resp)?).to_string();
+            let mut found: Vec<IndexEntry> = parse_directory_index(&index)
+                .into_iter()
+                .filter(|e| !e.is_directory && e.name.starts_with("fullfiletimelist-"))
+                .collect();
+            unambiguous_fullfiletimelist(found.iter().map(|e| e.name.clone()).collect(), base)?;
+            match found.pop() {
+                Some(e) => e.href,
+                _ => return Ok(None),
+            }
+        }
+    };
+
+    let url = format!("{}{}", base, name);
+    debug!("Downloading {}", url);
+
+    let resp = client.get(&url)?;
+    if !resp.status().is_success() {
+        warn!("Downloading '{}' returned status '{}'", url, resp.status());
+        return Ok(None);
+    }
+
+    let timestamp = resp
+        .headers()
+        .get("last-modified")
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| DateTime::parse_from_rfc2822(h).ok())
+        .map(|d| d.timestamp());
+    if let Some(t) = timestamp {
+        if fullfiletimelist_is_stale(&url, t, max_age_hours) {
+            return Ok(None);
+        }
+    }
+
+    Ok(Some(http::read_body(resp)?))
+}
+
 fn scan_local_directory(
     cds: &mut HashMap<String, CategoryDirectory>,
     excludes: &[String],
@@ -1301,37 +2286,15 @@ fn scan_local_directory(
     };
 
     if !fullfiletimelist.is_empty() && !skip_fftl {
-        debug::print_step(format!(
-            "Local directory ({}) scan using {}",
-            url, fullfiletimelist
-        ));
+        debug!("Local directory ({}) scan using {}", url, fullfiletimelist);
         let file = std::fs::File::open(fullfiletimelist)?;
         let data = unsafe { memmap::MmapOptions::new().map(&file)? };
-        for line in data.split(|elem| elem == &b'\n') {
-            let v: Vec<&str> = std::str::from_utf8(line)?.split('\t').collect();
-            if v.len() < 4 {
-                continue;
-            }
-            // For "Fedora Linux" category, remove "linux/" prefix (first 6 chars)
-            let path = if category_name == "Fedora Linux" && v[3].len() > 6 {
-                v[3][6..].to_string()
-            } else {
-                v[3].to_string()
-            };
-            let info = FileInfo {
-                is_directory: v[1].starts_with('d'),
-                is_readable: !v[1].contains('-'),
-                size: v[2].parse()?,
-                timestamp: v[0].parse()?,
-                name: Some(path),
-            };
-            add_entry_to_category_directories(info, cds, excludes, topdir);
-        }
+        parse_fullfiletimelist(&data, cds, excludes, topdir, category_name)?;
 
         return Ok(());
     }
 
-    debug::print_step(format!("Local directory ({}) scan", url));
+    debug!("Local directory ({}) scan", url);
     WalkDir::new(url)
         .into_iter()
         .filter_entry(is_not_hidden)
@@ -1373,208 +2336,956 @@ fn scan_with_rsync(
     rsync_options: &[String],
     url: &str,
 ) -> Result<(), Box<dyn Error>> {
-    debug::print_step(format!(
-        "Running rsync -r --no-human-readable {:?} {:?} {}",
-        rsync_options, category_rsync_options, url
-    ));
-
-    let output = Command::new("rsync")
-        // We always need '-r' and '--no-human-readable'
-        .arg("-r")
-        .arg("--no-human-readable")
-        .args(rsync_options)
-        .args(category_rsync_options)
-        .arg(url)
-        .output();
-
-    let pattern = Regex::new(r"([drwSsx-]{10})\s*(.*) (.*) (.*) (.*)")?;
-    let readable_pattern = Regex::new(r"^d......r.x").unwrap();
-
-    String::from_utf8(output?.stdout)?
-        .lines()
-        .filter_map(|line| pattern.captures(line))
-        .map(|info| FileInfo {
-            is_directory: info[1].starts_with('d'),
-            is_readable: readable_pattern.is_match(&info[1]),
-            size: info[2].parse().unwrap(),
-            timestamp: ctime_from_rsync(info[3].to_string(), info[4].to_string()),
-            name: info.get(5).map(|n| n.as_str().trim().to_string()),
-        })
-        .for_each(|x| {
-            add_entry_to_category_directories(x, cds, excludes, topdir);
-        });
-
-    Ok(())
+    // We always need '-r' and '--no-human-readable'
+    let mut args: Vec<String> = vec![String::from("-r"), String::from("--no-human-readable")];
+    args.extend_from_slice(rsync_options);
+    args.extend_from_slice(category_rsync_options);
+    args.push(url.to_string());
+
+    rsync::list(&args, |x| {
+        add_entry_to_category_directories(x, cds, excludes, topdir);
+    })
 }
 
-struct Parameters {
-    list_categories: bool,
-    category_specified: bool,
-    category_name: String,
-    delete_directories: bool,
-    config_file: String,
-    skip_fftl: bool,
+/// An entry of a HTTP(S) directory index as created by
+/// Apache's or nginx' autoindex.
+#[derive(Debug, Default, PartialEq)]
+struct IndexEntry {
+    /// The link target relative to the directory
+    href: String,
+    /// The decoded name of the entry
+    name: String,
+    is_directory: bool,
+    /// The size as shown in the index. Only set if the index
+    /// shows the exact size in bytes.
+    size: Option<i64>,
+    /// The size if the index only shows an abbreviated size like `1.2K`
+    approximate_size: Option<i64>,
+    timestamp: Option<i64>,
 }
 
-fn setup_params() -> Parameters {
-    let args: Vec<String> = env::args().collect();
-    let program = args[0].clone();
-    let mut opts = Options::new();
+/// Decode percent encoded characters and the most common HTML
+/// entities in a link of a directory index.
+fn decode_href(href: &str) -> String {
+    let href = href
+        .replace("&amp;", "&")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'");
+    let bytes = href.as_bytes();
+    let mut decoded: Vec<u8> = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            if let Some(b) = std::str::from_utf8(&bytes[i + 1..i + 3])
+                .ok()
+                .and_then(|h| u8::from_str_radix(h, 16).ok())
+            {
+                decoded.push(b);
+                i += 3;
+                continue;
+            }
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
 
-    let mut params = Parameters {
-        list_categories: false,
-        category_specified: false,
-        category_name: "".to_string(),
-        delete_directories: false,
-        config_file: String::from("/etc/mirrormanager/scan-primary-mirror.toml"),
-        skip_fftl: false,
-    };
+    String::from_utf8_lossy(&decoded).to_string()
+}
 
-    opts.optmulti(
-        "c",
-        "config",
-        &format!("configuration file ({})", params.config_file),
-        "CONFIG",
-    );
-    opts.optflagmulti("d", "debug", "enable debug");
-    opts.optflagmulti("", "list-categories", "list available catagories");
-    opts.optflagmulti(
-        "",
-        "delete-directories",
-        "delete directories from the database that no longer exist",
-    );
-    opts.optflagmulti(
-        "",
-        "skip-fullfiletimelist",
-        "do not look for a fullfiletimelist-*; actually scan the filesystem",
-    );
+/// Returns a timestamp from the date shown in a directory index.
+///
+/// Apache uses '2021-03-19 12:34' and nginx (and older Apache
+/// versions) '19-Mar-2021 12:34'. The time is interpreted as UTC.
+fn timestamp_from_index(date: &str) -> Option<i64> {
+    for f in [
+        "%Y-%m-%d %H:%M:%S",
+        "%Y-%m-%d %H:%M",
+        "%d-%b-%Y %H:%M:%S",
+        "%d-%b-%Y %H:%M",
+    ] {
+        if let Ok(d) = NaiveDateTime::parse_from_str(date, f) {
+            return Some(d.and_utc().timestamp());
+        }
+    }
 
-    opts.optmulti("", "category", "only scan category CATEGORY", "CATEGORY");
+    None
+}
 
-    let matches = match opts.parse(&args[1..]) {
-        Ok(m) => m,
-        _ => {
-            print!(
-                "{}",
-                opts.usage(format!("Usage: {} [options]", program).as_str())
-            );
-            process::exit(0);
-        }
+/// Returns the number of bytes of an abbreviated size like `1.2K`
+/// as shown by Apache's autoindex.
+fn approximate_size(size: &str) -> Option<i64> {
+    let unit: i64 = match size.chars().last()?.to_ascii_uppercase() {
+        'K' => 1 << 10,
+        'M' => 1 << 20,
+        'G' => 1 << 30,
+        'T' => 1 << 40,
+        'P' => 1 << 50,
+        _ => return None,
     };
+    let value = size[..size.len() - 1].parse::<f64>().ok()?;
 
-    if matches.opt_present("debug") {
-        debug::DEBUG.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
-    }
-
-    if matches.opt_present("list-categories") {
-        params.list_categories = true;
-    }
+    Some((value * unit as f64) as i64)
+}
 
-    if matches.opt_present("skip-fullfiletimelist") {
-        params.skip_fftl = true;
-    }
+/// Parse a HTML directory listing.
+///
+/// Links leaving the current directory (parent directory, absolute
+/// links, sort links) are ignored. Names, sizes and modification
+/// times are taken from the text following each link.
+fn parse_directory_index(html: &str) -> Vec<IndexEntry> {
+    let link = Regex::new(r#"(?i)<a\s[^>]*href\s*=\s*["']([^"']+)["'][^>]*>.*?</a>"#).unwrap();
+    let tag = Regex::new(r"<[^>]*>").unwrap();
+    let details = Regex::new(
+        r"^\s*(\d{4}-\d{2}-\d{2} \d{2}:\d{2}(?::\d{2})?|\d{2}-[A-Za-z]{3}-\d{4} \d{2}:\d{2}(?::\d{2})?)\s+(\S+)?",
+    )
+    .unwrap();
+
+    let links: Vec<_> = link.captures_iter(html).collect();
+    let mut entries: Vec<IndexEntry> = Vec::new();
+
+    for (i, l) in links.iter().enumerate() {
+        let href = l[1].to_string();
+        if href.starts_with('?')
+            || href.starts_with('#')
+            || href.starts_with('/')
+            || href.starts_with("..")
+            || href.starts_with("./")
+            || href.contains("://")
+            || href.trim_end_matches('/').contains('/')
+            || href == "."
+        {
+            continue;
+        }
 
-    if matches.opt_present("category") {
-        params.category_specified = true;
-        params.category_name =
-            matches.opt_strs("category")[matches.opt_count("category") - 1].to_string();
-    }
+        let end = l.get(0).unwrap().end();
+        let next = match links.get(i + 1) {
+            Some(n) => n.get(0).unwrap().start(),
+            _ => html.len(),
+        };
+        // Only look at the text up to the end of the current line
+        // or table row.
+        let text = tag.replace_all(&html[end..next], " ");
+        let line = text.lines().find(|t| !t.trim().is_empty()).unwrap_or("");
+
+        let is_directory = href.ends_with('/');
+        let mut entry = IndexEntry {
+            name: decode_href(href.trim_end_matches('/')),
+            href,
+            is_directory,
+            size: None,
+            approximate_size: None,
+            timestamp: None,
+        };
 
-    if matches.opt_present("delete-directories") {
-        params.delete_directories = true;
-    }
+        if let Some(d) = details.captures(line) {
+            entry.timestamp = timestamp_from_index(&d[1]);
+            if !is_directory {
+                entry.size = d.get(2).and_then(|s| s.as_str().parse::<i64>().ok());
+                if entry.size.is_none() {
+                    entry.approximate_size = d.get(2).and_then(|s| approximate_size(s.as_str()));
+                }
+            }
+        }
 
-    if matches.opt_present("config") {
-        params.config_file =
-            matches.opt_strs("config")[matches.opt_count("config") - 1].to_string();
+        entries.push(entry);
     }
 
-    params
+    entries
 }
 
-fn main() {
-    let mut category = db::functions::Category {
-        id: -1,
-        name: "".to_string(),
-        topdir: "".to_string(),
-        product_id: -1,
-    };
+/// Directories deeper than this are not crawled. This stops the
+/// crawl of directory loops created by symbolic links.
+const MAX_HTTP_DEPTH: usize = 64;
 
-    let params = setup_params();
+/// Scan the primary mirror by crawling HTML directory indexes.
+///
+/// Directories without a modification time in the index get the
+/// time of the newest file they contain. A HEAD request is used to
+/// get `Content-Length` and `Last-Modified` of a file if the index
+/// does not show its modification time. The exact size is only
+/// requested for files which need it: files in `repodata` directories
+/// and in directories containing one of the `checksum_files`. All
+/// other files get the abbreviated size of the index.
+fn scan_with_http(
+    cds: &mut HashMap<String, CategoryDirectory>,
+    excludes: &[String],
+    topdir: &str,
+    url: &str,
+    client: &http::Client,
+    checksum_files: &[glob::Pattern],
+) -> Result<(), Box<dyn Error>> {
+    debug!("Crawling directory index {}", url);
 
-    let settings = match Settings::new(params.config_file) {
-        Ok(s) => s,
-        Err(e) => {
-            println!("Configuration file parsing failed: {}", e);
-            process::exit(1);
-        }
+    let head = |u: &str| -> (Option<i64>, Option<i64>) {
+        let resp = match client.head(u) {
+            Ok(r) if r.status().is_success() => r,
+            _ => return (None, None),
+        };
+        let size = resp
+            .headers()
+            .get("content-length")
+            .and_then(|h| h.to_str().ok())
+            .and_then(|h| h.parse::<i64>().ok());
+        let timestamp = resp
+            .headers()
+            .get("last-modified")
+            .and_then(|h| h.to_str().ok())
+            .and_then(|h| DateTime::parse_from_rfc2822(h).ok())
+            .map(|d| d.timestamp());
+        (size, timestamp)
     };
 
-    let mut connection = match PgConnection::establish(&settings.database.url) {
-        Ok(c) => c,
-        Err(e) => {
-            println!("Connection to the database failed: {}", e);
-            process::exit(1);
-        }
+    let base = match url.ends_with('/') {
+        true => url.to_string(),
+        false => format!("{}/", url),
     };
 
-    let cl = db::functions::get_categories(&mut connection);
-
-    if params.list_categories {
-        list_categories(&cl);
-        process::exit(0);
+    // Directories without a timestamp in the index
+    let mut unknown_ctime: Vec<String> = Vec::new();
+    // The URLs of the directories already crawled
+    let mut visited: HashSet<String> = HashSet::new();
+    // (name relative to url, URL of the directory, depth)
+    let mut queue: Vec<(String, String, usize)> = vec![(String::new(), base, 0)];
+
+    add_entry_to_category_directories(
+        FileInfo {
+            is_directory: true,
+            is_readable: true,
+            size: 0,
+            timestamp: -1,
+            name: Some(String::from(".")),
+        },
+        cds,
+        excludes,
+        topdir,
+    );
+    if !cds.contains_key("") {
+        return Ok(());
     }
+    unknown_ctime.push(String::new());
 
-    if !params.category_specified {
-        println!("Please specify a category using '--category'\n");
-        list_categories(&cl);
-        process::exit(1);
-    }
-    for c in &cl {
-        if params.category_name == c.name {
-            category = c.clone();
+    while let Some((dir, dir_url, depth)) = queue.pop() {
+        if !visited.insert(dir_url.clone()) {
+            debug!("Already crawled {}", dir_url);
+            continue;
+        }
+        let resp = client.get(&dir_url)?;
+        if resp.status() == reqwest::StatusCode::FORBIDDEN {
+            if let Some(cd) = cds.get_mut(&dir) {
+                cd.readable = false;
+            }
+            continue;
+        }
+        if !resp.status().is_success() {
+            return Err(format!(
+                "Downloading '{}' returned status '{}'",
+                dir_url,
+                resp.status()
+            )
+            .into());
+        }
+
+        let index = String::from_utf8_lossy(&http::read_body(resp)?).to_string();
+        let entries = parse_directory_index(&index);
+        let exact_size = basename(dir.clone()) == "repodata"
+            || entries
+                .iter()
+                .any(|e| !e.is_directory && checksum::is_checksum_file(&e.name, checksum_files));
+        for e in entries {
+            if e.is_directory && depth >= MAX_HTTP_DEPTH {
+                warn!(
+                    "Not crawling {}{} deeper than {} directories",
+                    dir_url, e.href, MAX_HTTP_DEPTH
+                );
+                continue;
+            }
+            let name = match dir.is_empty() {
+                true => e.name.clone(),
+                false => format!("{}/{}", dir, e.name),
+            };
+            let entry_url = format!("{}{}", dir_url, e.href);
+            let (size, timestamp) = match (e.is_directory, e.size, e.timestamp) {
+                (true, _, t) => (Some(0), t),
+                (false, Some(s), Some(t)) => (Some(s), Some(t)),
+                (false, None, Some(t)) if !exact_size => (e.approximate_size, Some(t)),
+                (false, s, t) => {
+                    let (head_size, head_timestamp) = head(&entry_url);
+                    (s.or(head_size), t.or(head_timestamp))
+                }
+            };
+
+            add_entry_to_category_directories(
+                FileInfo {
+                    is_directory: e.is_directory,
+                    is_readable: true,
+                    size: size.unwrap_or(0),
+                    timestamp: timestamp.unwrap_or(-1),
+                    name: Some(name.clone()),
+                },
+                cds,
+                excludes,
+                topdir,
+            );
+
+            // Only descend into directories which have not been excluded
+            if e.is_directory && cds.contains_key(&name) {
+                if timestamp.is_none() {
+                    unknown_ctime.push(name.clone());
+                }
+                queue.push((name, entry_url, depth + 1));
+            }
         }
     }
 
-    if category.id == -1 {
-        println!(
-            "Category {} not found. Please use one of the following:\n",
-            params.category_name
-        );
-        list_categories(&cl);
-        process::exit(1);
-    }
-
-    // Now we know that the category specified exists in the database also check if
-    // it exists in the configuration file
-    let config_file_categories = match &settings.category {
-        Some(c) => c,
-        _ => {
-            println!("No categories found in the configuration file");
-            process::exit(1);
+    for d in unknown_ctime {
+        if let Some(cd) = cds.get_mut(&d) {
+            cd.ctime = cd.files.iter().map(|f| f.timestamp).max().unwrap_or(0);
+        }
+    }
+
+    Ok(())
+}
+
+/// Write `fullfiletimelist-*` and `imagelist-*` of a category scanned
+/// on the local file system.
+///
+/// The files are written to the same location `scan_local_directory()`
+/// expects the `fullfiletimelist-*`. With `--dry-run` nothing is
+/// written, only the files which would be updated are logged.
+fn write_filelists(
+    cds: &HashMap<String, CategoryDirectory>,
+    category: &settings::Category,
+    backend: &dyn backend::ScanBackend,
+) -> Result<(), Box<dyn Error>> {
+    let url = match backend.local_directory() {
+        Some(u) => u,
+        None => {
+            warn!(
+                "Cannot write filelists for category '{}' of type '{}'. Skipping.",
+                category.name,
+                backend.name()
+            );
+            return Ok(());
         }
     };
 
-    let mut config_file_category = settings::Category::default();
+    // For "Fedora Linux" category, fullfiletimelist is one directory up
+    let (dir, prefix) = match category.name == "Fedora Linux" {
+        true => (format!("{}/..", url), "linux/"),
+        false => (url.to_string(), ""),
+    };
 
-    for c in config_file_categories {
-        if c.name == params.category_name {
-            config_file_category = c.clone();
+    for (name, setting, content) in [
+        (
+            &category.fullfiletimelist,
+            "fullfiletimelist",
+            filelist::fullfiletimelist(cds, prefix),
+        ),
+        (
+            &category.imagelist,
+            "imagelist",
+            filelist::imagelist(cds, prefix),
+        ),
+    ] {
+        let name = match name {
+            Some(n) => n,
+            _ => {
+                warn!(
+                    "No '{}' configured for category '{}'. Skipping.",
+                    setting, category.name
+                );
+                continue;
+            }
+        };
+        let path = Path::new(&dir).join(name);
+        if dry_run::is_enabled() {
+            match filelist::is_changed(&path, &content) {
+                true => info!("Would update {}", path.display()),
+                false => debug!("{} is unchanged", path.display()),
+            }
+            continue;
+        }
+        match filelist::write_if_changed(&path, &content)? {
+            true => info!("Updated {}", path.display()),
+            false => debug!("{} is unchanged", path.display()),
         }
     }
 
-    if config_file_category.name.is_empty() {
-        println!(
-            "Category '{}' not found in configuration file",
-            params.category_name
-        );
-        process::exit(1);
+    Ok(())
+}
+
+/// Run one phase of writing a category scan to the database.
+///
+/// With `savepoint` the phase runs in its own savepoint which is
+/// rolled back if the phase fails.
+fn run_phase<T>(
+    c: &mut PgConnection,
+    savepoint: bool,
+    name: &str,
+    phase: impl FnOnce(&mut PgConnection) -> Result<T, Box<dyn Error>>,
+) -> Result<T, Box<dyn Error>> {
+    let start = std::time::Instant::now();
+    let result = match savepoint {
+        true => c.transaction(phase),
+        false => phase(c),
+    };
+    report::phase_duration(name, start.elapsed());
+
+    result.map_err(|e| format!("{} failed {}", name, e).into())
+}
+
+/// Tables read once and shared by the scans of all categories.
+#[derive(Clone)]
+struct DatabaseCache {
+    arches: Vec<db::models::Arch>,
+    versions: Vec<db::models::Version>,
+    fds: Vec<db::models::FileDetail>,
+    /// The optional table `file_detail_digest` exists
+    digest_table: bool,
+}
+
+impl DatabaseCache {
+    fn new(c: &mut PgConnection) -> Result<Self, diesel::result::Error> {
+        Ok(DatabaseCache {
+            arches: db::functions::get_arches(c)?,
+            versions: db::functions::get_versions(c)?,
+            fds: db::functions::get_file_details(c),
+            digest_table: db::functions::table_exists(c, "file_detail_digest")?,
+        })
     }
+}
 
-    let category_rsync_options: Vec<String> = match &config_file_category.options {
-        Some(opt) => opt.split(' ').map(str::to_string).collect::<Vec<String>>(),
+/// Parameter for the `scan_category()` and `update_database()` functions.
+struct ScanCategory<'a> {
+    settings: &'a Settings,
+    params: &'a Parameters,
+    /// The category as found in the database
+    category: &'a db::functions::Category,
+    /// The category as found in the configuration file
+    config_file_category: &'a settings::Category,
+    /// Caches shared by all categories
+    caches: &'a Caches,
+}
+
+/// The caches shared by all categories. They are
+/// written to disk at the end of the run.
+struct Caches {
+    /// Checksums of local files
+    checksums: verify::Cache,
+    /// Details of files downloaded from `checksum_base`
+    details: details_cache::Cache,
+}
+
+/// Parameter for the `database_cleanup()` function.
+struct DatabaseCleanup<'a> {
+    /// The directories which no longer exist
+    gone: &'a [&'a db::models::Directory],
+    /// Number of directories of the category before the scan
+    total: usize,
+    max_count: usize,
+    max_percent: f64,
+    force: bool,
+    repositories: &'a [db::models::Repository],
+    digest_table: bool,
+    soft_delete_repositories: bool,
+}
+
+/// Remove the directories which no longer exist from the database.
+///
+/// If this exceeds the safety limits of `check_delete_limits()` nothing
+/// is removed and the reason is returned. The changes of the other
+/// phases of the scan are kept.
+fn database_cleanup(
+    c: &mut PgConnection,
+    savepoint: bool,
+    p: &DatabaseCleanup,
+) -> Result<Option<String>, Box<dyn Error>> {
+    if let Err(e) = check_delete_limits(p.gone, p.total, p.max_count, p.max_percent, p.force) {
+        warn!("{} Skipping the database cleanup.", e);
+        return Ok(Some(e));
+    }
+
+    run_phase(c, savepoint, "Database cleanup", |c| {
+        Ok(cleanup_database(
+            c,
+            p.gone,
+            p.repositories,
+            p.digest_table,
+            p.soft_delete_repositories,
+        )?)
+    })?;
+
+    Ok(None)
+}
+
+/// Parameter for the `update_database()` function.
+struct UpdateDatabase<'a> {
+    topdir: &'a str,
+    /// The backend used to scan the primary mirror
+    backend: &'a dyn ScanBackend,
+    /// If set only signed checksum files are used
+    keyring: Option<&'a signature::Keyring>,
+    /// If set checksum files are verified against the local files
+    verifier: Option<&'a verify::Verifier<'a>>,
+    /// Digests stored in `file_detail_digest` for new entries
+    extra_digests: &'a [digests::Extra],
+    /// File name patterns of checksum files
+    checksum_files: &'a [glob::Pattern],
+    /// The directories of the category in the database
+    directories: Vec<db::models::Directory>,
+    /// Retrieved by `fetch_file_details()` before the transaction
+    fetched: HashMap<(String, String), Fetched>,
+}
+
+/// Write the result of a category scan to the database.
+///
+/// This is expected to run inside the transaction of the category.
+/// The phases are syncing the directories, finding repositories,
+/// aging `file_detail` entries and (optional) removing directories
+/// which no longer exist. Returns why removing the directories has
+/// been skipped.
+fn update_database(
+    c: &mut PgConnection,
+    s: &ScanCategory,
+    p: &mut UpdateDatabase,
+    cds: &mut HashMap<String, CategoryDirectory>,
+    cache: &mut DatabaseCache,
+) -> Result<Option<String>, Box<dyn Error>> {
+    let settings = s.settings;
+    let category = s.category;
+    let savepoint = s.params.phase_savepoints;
+    let topdir = p.topdir;
+    let mut d = std::mem::take(&mut p.directories);
+    // The directories added by this scan do not count for the delete limits
+    let known_directories = d.len();
+
+    run_phase(c, savepoint, "Syncing changes to database", |c| {
+        sync_category_directories(c, topdir.to_string(), category.id, &mut d, cds)
+    })?;
+
+    let repositories = match db::functions::get_repositories(c) {
+        Ok(r) => r,
+        Err(e) => {
+            return Err(format!("Reading repositories from the database failed: {:#?}", e).into())
+        }
+    };
+    let repository_mappings: Vec<settings::RepositoryMapping> = match &settings.repository_mapping {
+        Some(rm) => rm.to_vec(),
+        _ => Vec::new(),
+    };
+
+    let repository_aliases: Vec<settings::RepositoryAlias> = match &settings.repository_aliases {
+        Some(ra) => ra.to_vec(),
+        _ => Vec::new(),
+    };
+
+    let skip_paths: Vec<String> = match &settings.skip_paths_for_version {
+        Some(ex) => ex.to_vec(),
+        _ => vec![],
+    };
+    let test_paths: Vec<String> = match &settings.test_paths {
+        Some(ex) => ex.to_vec(),
+        _ => vec![],
+    };
+    let skip_repository_paths: Vec<String> = match &settings.skip_repository_paths {
+        Some(ex) => ex.to_vec(),
         _ => vec![],
     };
+    let do_not_display_paths: Vec<String> = match &settings.do_not_display_paths {
+        Some(ex) => ex.to_vec(),
+        _ => vec![],
+    };
+    run_phase(c, savepoint, "Creating repositories in database", |c| {
+        find_repositories(&mut FindRepositories {
+            c,
+            cds,
+            backend: p.backend,
+            top: topdir.to_string(),
+            cat: category,
+            repos: &repositories,
+            rms: &repository_mappings,
+            fds: &mut cache.fds,
+            arches: &cache.arches,
+            versions: &mut cache.versions,
+            skip_paths: &skip_paths,
+            test_paths: &test_paths,
+            skip_repository_paths: &skip_repository_paths,
+            do_not_display_paths: &do_not_display_paths,
+            aliases: &repository_aliases,
+            repodata_details: settings.repodata_file_details.unwrap_or(false),
+            keyring: p.keyring,
+            verifier: p.verifier,
+            extra_digests: p.extra_digests,
+            checksum_files: p.checksum_files,
+            details_cache: &s.caches.details,
+            fetched: std::mem::take(&mut p.fetched),
+            digest_table: cache.digest_table,
+        })
+    })?;
+
+    run_phase(c, savepoint, "File Detail aging", |c| {
+        Ok(age_file_details(
+            c,
+            &mut cache.fds,
+            &d,
+            cache.digest_table,
+            settings.max_stale_days.unwrap_or(3),
+            settings.max_propagation_days.unwrap_or(2),
+        )?)
+    })?;
+
+    if !s.params.delete_directories {
+        return Ok(None);
+    }
+
+    let gone = gone_directories(cds, &d, topdir);
+    database_cleanup(
+        c,
+        savepoint,
+        &DatabaseCleanup {
+            gone: &gone,
+            total: known_directories,
+            max_count: settings.delete_directories_max_count.unwrap_or(1000),
+            max_percent: settings.delete_directories_max_percent.unwrap_or(10.0),
+            force: s.params.force,
+            repositories: &repositories,
+            digest_table: cache.digest_table,
+            soft_delete_repositories: settings.soft_delete_repositories.unwrap_or(false),
+        },
+    )
+}
+
+/// Select the categories to scan with their entry in the configuration file.
+///
+/// * `cl` - the categories in the database
+/// * `config_file_categories` - the categories in the configuration file
+/// * `names` - the categories given with `--category`. A category given
+///   more than once is scanned once.
+/// * `all` - scan all categories found in the database and in the
+///   configuration file
+fn select_categories(
+    cl: &[db::functions::Category],
+    config_file_categories: &[settings::Category],
+    names: &[String],
+    all: bool,
+) -> Result<Vec<(db::functions::Category, settings::Category)>, Box<dyn Error>> {
+    if config_file_categories.is_empty() {
+        return Err("No categories found in the configuration file".into());
+    }
+
+    let mut categories: Vec<(db::functions::Category, settings::Category)> = Vec::new();
+
+    if all {
+        for c in cl {
+            if let Some(cfc) = config_file_categories.iter().find(|cfc| cfc.name == c.name) {
+                categories.push((c.clone(), cfc.clone()));
+            }
+        }
+    }
+
+    for name in names {
+        if categories.iter().any(|(c, _)| c.name == *name) {
+            continue;
+        }
+
+        let category = match cl.iter().find(|c| c.name == *name) {
+            Some(c) => c.clone(),
+            _ => {
+                return Err(format!(
+                    "Category {} not found. Please use one of the following:\n",
+                    name
+                )
+                .into())
+            }
+        };
+
+        // Now we know that the category specified exists in the database also check if
+        // it exists in the configuration file
+        let config_file_category = match config_file_categories.iter().find(|c| c.name == *name) {
+            Some(c) => c.clone(),
+            _ => return Err(format!("Category '{}' not found in configuration file", name).into()),
+        };
+
+        categories.push((category, config_file_category));
+    }
+
+    Ok(categories)
+}
+
+/// Exit code used if categories have been skipped because another
+/// process is scanning them
+const LOCKED_EXIT_CODE: i32 = 2;
+
+/// Take the lock of each category to be scanned.
+///
+/// Waits up to `timeout` seconds for other scans of the same
+/// categories to finish. Returns the indices of the categories in
+/// `categories` which are still locked by another process.
+fn lock_categories(
+    c: &mut PgConnection,
+    categories: &[(db::functions::Category, settings::Category)],
+    timeout: u64,
+) -> Result<Vec<usize>, Box<dyn Error>> {
+    let start = std::time::Instant::now();
+    let mut locked: Vec<usize> = Vec::new();
+
+    for (i, (category, _)) in categories.iter().enumerate() {
+        while !db::functions::try_lock_category(c, category.id)? {
+            if start.elapsed().as_secs() >= timeout {
+                warn!(
+                    "Category '{}' is being scanned by another process. Skipping.",
+                    category.name
+                );
+                locked.push(i);
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_secs(1));
+        }
+    }
+
+    Ok(locked)
+}
+
+struct Parameters {
+    list_categories: bool,
+    category_names: Vec<String>,
+    all_categories: bool,
+    delete_directories: bool,
+    /// Delete directories even above the safety limits
+    force: bool,
+    config_file: String,
+    skip_fftl: bool,
+    write_filelists: bool,
+    /// Output format of the dry-run report if `--dry-run` is used
+    dry_run_format: Option<String>,
+    phase_savepoints: bool,
+    /// Number of categories scanned in parallel
+    jobs: usize,
+    /// Seconds to wait for another scan of the same category to finish
+    lock_timeout: u64,
+    /// Write a JSON report of the run to this file
+    report_json: Option<String>,
+    /// Write metrics of the run to this node-exporter textfile
+    metrics_textfile: Option<String>,
+}
+
+fn setup_params() -> Parameters {
+    let args: Vec<String> = env::args().collect();
+    let program = args[0].clone();
+    let mut opts = Options::new();
+
+    let mut params = Parameters {
+        list_categories: false,
+        category_names: Vec::new(),
+        all_categories: false,
+        delete_directories: false,
+        force: false,
+        config_file: String::from("/etc/mirrormanager/scan-primary-mirror.toml"),
+        skip_fftl: false,
+        write_filelists: false,
+        dry_run_format: None,
+        phase_savepoints: false,
+        jobs: 1,
+        lock_timeout: 0,
+        report_json: None,
+        metrics_textfile: None,
+    };
+
+    opts.optmulti(
+        "c",
+        "config",
+        &format!("configuration file ({})", params.config_file),
+        "CONFIG",
+    );
+    opts.optflagmulti(
+        "d",
+        "debug",
+        "enable debug output (use twice to also enable trace output)",
+    );
+    opts.optopt(
+        "",
+        "log-format",
+        "format of the log output (FORMAT: text or json)",
+        "FORMAT",
+    );
+    opts.optflagmulti("", "list-categories", "list available catagories");
+    opts.optflagmulti(
+        "",
+        "delete-directories",
+        "delete directories from the database that no longer exist",
+    );
+    opts.optflagmulti(
+        "",
+        "force",
+        "delete directories even if more than the configured limit are missing",
+    );
+    opts.optflagmulti(
+        "",
+        "skip-fullfiletimelist",
+        "do not look for a fullfiletimelist-*; actually scan the primary mirror",
+    );
+    opts.optflagmulti(
+        "",
+        "write-filelists",
+        "write fullfiletimelist-* and imagelist-* of a directory category",
+    );
+
+    opts.optflagmulti(
+        "",
+        "phase-savepoints",
+        "commit the successful phases of a scan even if a later phase fails",
+    );
+    opts.optopt(
+        "",
+        "report-json",
+        "write a JSON report of the run to FILE",
+        "FILE",
+    );
+    opts.optopt(
+        "",
+        "metrics-textfile",
+        "write metrics of each category to the node-exporter textfile FILE",
+        "FILE",
+    );
+    opts.optflagopt(
+        "",
+        "dry-run",
+        "report database changes instead of writing them (FORMAT: table or json)",
+        "FORMAT",
+    );
+
+    opts.optmulti(
+        "",
+        "category",
+        "scan category CATEGORY (can be used multiple times)",
+        "CATEGORY",
+    );
+    opts.optopt(
+        "j",
+        "jobs",
+        &format!("number of categories scanned in parallel ({})", params.jobs),
+        "N",
+    );
+    opts.optopt(
+        "",
+        "lock-timeout",
+        &format!(
+            "seconds to wait if a category is scanned by another process ({})",
+            params.lock_timeout
+        ),
+        "SECONDS",
+    );
+    opts.optflagmulti(
+        "",
+        "all-categories",
+        "scan all categories from the database and the configuration file",
+    );
+
+    let matches = match opts.parse(&args[1..]) {
+        Ok(m) => m,
+        _ => {
+            print!(
+                "{}",
+                opts.usage(format!("Usage: {} [options]", program).as_str())
+            );
+            process::exit(0);
+        }
+    };
+
+    let log_format = matches
+        .opt_str("log-format")
+        .unwrap_or_else(|| String::from("text"));
+    if log_format != "text" && log_format != "json" {
+        println!("Unknown log format '{}'", log_format);
+        process::exit(1);
+    }
+    logging::init(matches.opt_count("debug"), log_format == "json");
+
+    if matches.opt_present("list-categories") {
+        params.list_categories = true;
+    }
+
+    if matches.opt_present("skip-fullfiletimelist") {
+        params.skip_fftl = true;
+    }
+
+    if matches.opt_present("write-filelists") {
+        params.write_filelists = true;
+    }
+
+    params.category_names = matches.opt_strs("category");
+
+    if matches.opt_present("all-categories") {
+        params.all_categories = true;
+    }
+
+    if let Some(j) = matches.opt_str("jobs") {
+        params.jobs = match j.parse::<usize>() {
+            Ok(j) if j > 0 => j,
+            _ => {
+                error!("'--jobs' needs to be a number greater than 0");
+                process::exit(1);
+            }
+        };
+    }
+
+    if let Some(t) = matches.opt_str("lock-timeout") {
+        params.lock_timeout = match t.parse::<u64>() {
+            Ok(t) => t,
+            _ => {
+                error!("'--lock-timeout' needs to be a number");
+                process::exit(1);
+            }
+        };
+    }
+
+    if matches.opt_present("dry-run") {
+        let format = matches
+            .opt_str("dry-run")
+            .unwrap_or_else(|| String::from("table"));
+        if format != "table" && format != "json" {
+            error!("Unknown dry-run format '{}'", format);
+            process::exit(1);
+        }
+        dry_run::set_enabled(true);
+        params.dry_run_format = Some(format);
+    }
+
+    params.report_json = matches.opt_str("report-json");
+    params.metrics_textfile = matches.opt_str("metrics-textfile");
+
+    if matches.opt_present("phase-savepoints") {
+        params.phase_savepoints = true;
+    }
+
+    if matches.opt_present("delete-directories") {
+        params.delete_directories = true;
+    }
+
+    if matches.opt_present("force") {
+        params.force = true;
+    }
+
+    if matches.opt_present("config") {
+        params.config_file =
+            matches.opt_strs("config")[matches.opt_count("config") - 1].to_string();
+    }
+
+    params
+}
+
+/// Scan one category and write the result to the database.
+fn scan_category(
+    c: &mut PgConnection,
+    s: &ScanCategory,
+    cache: &mut DatabaseCache,
+) -> Result<(), Box<dyn Error>> {
+    let settings = s.settings;
+    let params = s.params;
+    let category = s.category;
+    let config_file_category = s.config_file_category;
 
     let rsync_options: Vec<String> = match &settings.common_rsync_options {
         Some(ro) => ro.split(' ').map(str::to_string).collect::<Vec<String>>(),
@@ -1601,133 +3312,451 @@ fn main() {
         false => format!("{}/", category.topdir),
     };
 
-    if let Err(e) = match config_file_category.r#type.as_str() {
-        "rsync" => scan_with_rsync(
-            &mut cds,
-            &excludes,
-            &topdir,
-            &category_rsync_options,
-            &rsync_options,
-            &config_file_category.url,
-        ),
-        "directory" => scan_local_directory(
-            &mut cds,
-            &excludes,
-            &topdir,
-            &config_file_category.url,
-            params.skip_fftl,
-            &config_file_category.name,
-        ),
-        _ => {
-            println!(
-                "Cannot handle type '{}' of category '{}'",
-                config_file_category.r#type, params.category_name
-            );
-            process::exit(1);
-        }
-    } {
-        println!("Scanning {} failed with {}", config_file_category.url, e);
-        process::exit(1);
+    let checksum_files = checksum::patterns(
+        config_file_category
+            .checksum_files
+            .as_ref()
+            .or(settings.checksum_files.as_ref())
+            .map(Vec::as_slice),
+    )?;
+
+    let backend = backend::new(
+        config_file_category,
+        &rsync_options,
+        settings.max_fullfiletimelist_hours.unwrap_or(24),
+        &topdir,
+        // The filelists are created from an actual scan of the file system
+        params.skip_fftl || params.write_filelists,
+        &settings::Http::merge(settings.http.as_ref(), config_file_category.http.as_ref()),
+        &checksum_files,
+    )?;
+
+    let keyring = match &config_file_category.keyring {
+        Some(k) => Some(signature::Keyring::load(k)?),
+        _ => None,
+    };
+
+    let start = std::time::Instant::now();
+    if let Err(e) = backend.scan(&mut cds, &excludes, &topdir) {
+        return Err(format!("Scanning {} failed with {}", config_file_category.url, e).into());
     }
+    report::phase_duration("Scanning", start.elapsed());
+    report::scanned_directories(cds.len());
 
     handle_unreadable(&mut cds);
 
-    let mut d = db::functions::get_directories(&mut connection, category.id);
+    if params.write_filelists {
+        if let Err(e) = write_filelists(&cds, config_file_category, backend.as_ref()) {
+            return Err(format!("Writing filelists failed {}", e).into());
+        }
+    }
 
-    if let Err(e) = sync_category_directories(
-        &mut connection,
-        topdir.clone(),
-        category.id,
-        &mut d,
-        &mut cds,
-    ) {
-        println!("Syncing changes to database failed {}", e);
-        process::exit(1);
+    let verifier = match config_file_category.verify_checksums {
+        Some(true) => Some(verify::Verifier {
+            cache: &s.caches.checksums,
+            max_size: settings.verify_checksums_max_size.unwrap_or(u64::MAX),
+            jobs: settings
+                .verify_checksums_jobs
+                .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |n| n.get())),
+        }),
+        _ => None,
+    };
+    let mut extra_digests =
+        digests::extra_algorithms(settings.extra_digests.as_deref().unwrap_or_default())?;
+    if !extra_digests.is_empty() && !cache.digest_table {
+        warn!("Table file_detail_digest does not exist. Not computing extra_digests.");
+        extra_digests.clear();
     }
 
-    let repositories = match db::functions::get_repositories(&mut connection) {
-        Ok(r) => r,
+    // Checksum files and repomd.xml are downloaded before the transaction
+    // is opened. Otherwise the rows written by the scan would stay locked
+    // for the whole download.
+    let directories = db::functions::get_directories(c, category.id);
+    mark_changed_directories(&mut cds, &directories, &topdir);
+    let start = std::time::Instant::now();
+    let fetched = fetch_file_details(&FetchFileDetails {
+        cds: &cds,
+        backend: backend.as_ref(),
+        top: &topdir,
+        keyring: keyring.as_ref(),
+        verifier: verifier.as_ref(),
+        extra_digests: &extra_digests,
+        checksum_files: &checksum_files,
+        details_cache: &s.caches.details,
+        fetch_jobs: settings.fetch_jobs.unwrap_or(4),
+    });
+    report::phase_duration("Fetching checksums", start.elapsed());
+
+    let mut update = UpdateDatabase {
+        topdir: &topdir,
+        backend: backend.as_ref(),
+        keyring: keyring.as_ref(),
+        verifier: verifier.as_ref(),
+        extra_digests: &extra_digests,
+        checksum_files: &checksum_files,
+        directories,
+        fetched,
+    };
+
+    // Everything of the category is written in one transaction. If the scan
+    // fails the database is not changed at all.
+    let mut phase_failure: Option<Box<dyn Error>> = None;
+    // Exceeding the delete limits fails the scan after everything
+    // else has been committed
+    let mut cleanup_skipped: Option<Box<dyn Error>> = None;
+    let result = c.transaction::<_, Box<dyn Error>, _>(|c| {
+        match update_database(c, s, &mut update, &mut cds, cache) {
+            Ok(skipped) => {
+                cleanup_skipped = skipped.map(Into::into);
+                Ok(())
+            }
+            // With savepoints only the failed phase has been rolled back.
+            // The phases before it are committed.
+            Err(e) if params.phase_savepoints => {
+                phase_failure = Some(e);
+                Ok(())
+            }
+            Err(e) => Err(e),
+        }
+    });
+
+    match result.err().or(phase_failure).or(cleanup_skipped) {
+        Some(e) => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// The result of scanning one category
+struct CategoryResult {
+    name: String,
+    result: Result<(), String>,
+    /// The category has not been scanned because another process is
+    /// scanning it. `result` contains the reason.
+    skipped: bool,
+    dry_run_changes: Vec<dry_run::Change>,
+    report: report::CategoryReport,
+}
+
+impl CategoryResult {
+    /// The result of a category locked by another process
+    fn skipped(name: &str) -> Self {
+        let reason = String::from("Category is being scanned by another process");
+        CategoryResult {
+            name: name.to_string(),
+            skipped: true,
+            dry_run_changes: Vec::new(),
+            report: report::skipped(name, &reason),
+            result: Err(reason),
+        }
+    }
+}
+
+/// Scan categories until all categories have been scanned.
+///
+/// `next` is the index of the next category in `categories` to scan.
+/// It is shared between all workers running in parallel. Returns the
+/// index and the result of each scanned category.
+fn scan_worker(
+    c: &mut PgConnection,
+    mut cache: DatabaseCache,
+    settings: &Settings,
+    params: &Parameters,
+    categories: &[(db::functions::Category, settings::Category)],
+    caches: &Caches,
+    next: &AtomicUsize,
+) -> Vec<(usize, CategoryResult)> {
+    let mut results: Vec<(usize, CategoryResult)> = Vec::new();
+
+    loop {
+        let i = next.fetch_add(1, Ordering::SeqCst);
+        let (category, config_file_category) = match categories.get(i) {
+            Some(c) => c,
+            _ => break,
+        };
+
+        let start = std::time::Instant::now();
+        logging::set_category(Some(&category.name));
+        let result = scan_category(
+            c,
+            &ScanCategory {
+                settings,
+                params,
+                category,
+                config_file_category,
+                caches,
+            },
+            &mut cache,
+        )
+        .map_err(|e| e.to_string());
+        report::phase_duration("Total", start.elapsed());
+
+        if let Err(e) = &result {
+            error!("{}", e);
+            // The cache might contain entries which have been rolled back
+            match DatabaseCache::new(c) {
+                Ok(new_cache) => cache = new_cache,
+                Err(e) => error!("Reading from the database failed: {}", e),
+            }
+        }
+
+        results.push((
+            i,
+            CategoryResult {
+                name: category.name.clone(),
+                skipped: false,
+                dry_run_changes: dry_run::take(),
+                report: report::take(&category.name, &result),
+                result,
+            },
+        ));
+        logging::set_category(None);
+    }
+
+    results
+}
+
+/// The table printed at the end of a scan of several categories
+fn summary(results: &[CategoryResult]) -> prettytable::Table {
+    let mut table = prettytable::Table::new();
+    table.set_format(*format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
+
+    table.set_titles(row!["Category Name", "Result"]);
+
+    for r in results {
+        match &r.result {
+            Ok(_) => table.add_row(row![r.name, "OK"]),
+            Err(e) if r.skipped => table.add_row(row![r.name, format!("SKIPPED: {}", e)]),
+            Err(e) => table.add_row(row![r.name, format!("FAILED: {}", e)]),
+        };
+    }
+
+    table
+}
+
+/// The exit code of a scan of `results`.
+///
+/// Failed categories take precedence over skipped categories.
+fn exit_code(results: &[CategoryResult]) -> i32 {
+    if results.iter().any(|r| r.result.is_err() && !r.skipped) {
+        return 1;
+    }
+    if results.iter().any(|r| r.skipped) {
+        return LOCKED_EXIT_CODE;
+    }
+
+    0
+}
+
+fn main() {
+    let started = chrono::offset::Local::now().timestamp();
+    let params = setup_params();
+
+    let settings = match Settings::new(params.config_file.clone()) {
+        Ok(s) => s,
         Err(e) => {
-            println!("Reading repositories from the database failed: {:#?}", e);
+            error!("Configuration file parsing failed: {}", e);
             process::exit(1);
         }
     };
-    let repository_mappings: Vec<settings::RepositoryMapping> = match &settings.repository_mapping {
-        Some(rm) => rm.to_vec(),
-        _ => Vec::new(),
-    };
 
-    let repository_aliases: Vec<settings::RepositoryAlias> = match &settings.repository_aliases {
-        Some(ra) => ra.to_vec(),
-        _ => Vec::new(),
+    let mut connection = match PgConnection::establish(&settings.database.url) {
+        Ok(c) => c,
+        Err(e) => {
+            error!("Connection to the database failed: {}", e);
+            process::exit(1);
+        }
     };
 
-    let skip_paths: Vec<String> = match &settings.skip_paths_for_version {
-        Some(ex) => ex.to_vec(),
-        _ => vec![],
+    let cl = db::functions::get_categories(&mut connection);
+
+    if params.list_categories {
+        list_categories(&cl);
+        process::exit(0);
+    }
+
+    if params.category_names.is_empty() && !params.all_categories {
+        error!("Please specify a category using '--category' or '--all-categories'\n");
+        list_categories(&cl);
+        process::exit(1);
+    }
+
+    let categories = match select_categories(
+        &cl,
+        settings.category.as_deref().unwrap_or_default(),
+        &params.category_names,
+        params.all_categories,
+    ) {
+        Ok(c) => c,
+        Err(e) => {
+            error!("{}", e);
+            list_categories(&cl);
+            process::exit(1);
+        }
     };
-    let test_paths: Vec<String> = match &settings.test_paths {
-        Some(ex) => ex.to_vec(),
-        _ => vec![],
+
+    // The locks are held by this connection until the process exits
+    let locked = match lock_categories(&mut connection, &categories, params.lock_timeout) {
+        Ok(l) => l,
+        Err(e) => {
+            error!("Locking categories failed: {}", e);
+            process::exit(1);
+        }
     };
-    let skip_repository_paths: Vec<String> = match &settings.skip_repository_paths {
-        Some(ex) => ex.to_vec(),
-        _ => vec![],
+
+    // Categories locked by another process are skipped. The index of
+    // each category in `categories` is kept to report in that order.
+    let mut skipped: Vec<(usize, CategoryResult)> = Vec::new();
+    let mut indices: Vec<usize> = Vec::new();
+    let mut scanned: Vec<(db::functions::Category, settings::Category)> = Vec::new();
+    for (i, c) in categories.into_iter().enumerate() {
+        match locked.contains(&i) {
+            true => skipped.push((i, CategoryResult::skipped(&c.0.name))),
+            false => {
+                indices.push(i);
+                scanned.push(c);
+            }
+        }
+    }
+    let categories = scanned;
+
+    let cache = match DatabaseCache::new(&mut connection) {
+        Ok(c) => c,
+        Err(e) => {
+            error!("Reading from the database failed: {}", e);
+            process::exit(1);
+        }
     };
-    let do_not_display_paths: Vec<String> = match &settings.do_not_display_paths {
-        Some(ex) => ex.to_vec(),
-        _ => vec![],
+
+    let caches = Caches {
+        checksums: verify::Cache::load(settings.verify_checksums_cache.as_deref()),
+        details: details_cache::Cache::load(settings.details_cache.as_deref()),
     };
-    let mut fds = db::functions::get_file_details(&mut connection);
-    let mut find_parameter = FindRepositories {
-        c: &mut connection,
-        cds: &mut cds,
-        checksum_base: match config_file_category.r#type.as_str() {
-            "rsync" => config_file_category.checksum_base,
-            "directory" => Some(
-                config_file_category
-                    .url
-                    .split(&topdir)
-                    .map(str::to_string)
-                    .collect::<Vec<String>>()[0]
-                    .clone(),
-            ),
-            _ => None,
-        },
-        top: topdir.clone(),
-        cat: &category,
-        repos: &repositories,
-        rms: &repository_mappings,
-        fds: &mut fds,
-        skip_paths: &skip_paths,
-        test_paths: &test_paths,
-        skip_repository_paths: &skip_repository_paths,
-        do_not_display_paths: &do_not_display_paths,
-        backend: config_file_category.r#type,
-        aliases: &repository_aliases,
+
+    let jobs = params.jobs.clamp(1, categories.len().max(1));
+    let next = AtomicUsize::new(0);
+    let mut results: Vec<(usize, CategoryResult)> = match jobs {
+        1 => scan_worker(
+            &mut connection,
+            cache,
+            &settings,
+            &params,
+            &categories,
+            &caches,
+            &next,
+        ),
+        _ => std::thread::scope(|s| {
+            let workers: Vec<_> = (0..jobs)
+                .map(|_| {
+                    s.spawn(|| {
+                        // Each worker uses its own connection to the database
+                        let mut c = match PgConnection::establish(&settings.database.url) {
+                            Ok(c) => c,
+                            Err(e) => {
+                                error!("Connection to the database failed: {}", e);
+                                return Vec::new();
+                            }
+                        };
+                        scan_worker(
+                            &mut c,
+                            cache.clone(),
+                            &settings,
+                            &params,
+                            &categories,
+                            &caches,
+                            &next,
+                        )
+                    })
+                })
+                .collect();
+            workers
+                .into_iter()
+                .flat_map(|w| w.join().unwrap_or_default())
+                .collect()
+        }),
     };
-    if let Err(e) = find_repositories(&mut find_parameter) {
-        println!("Creating repositories in database failed {}", e);
-        process::exit(1);
+
+    if let Err(e) = caches.checksums.save() {
+        warn!("Writing the checksum cache failed: {}", e);
+    }
+    if let Err(e) = caches.details.save() {
+        warn!("Writing the details cache failed: {}", e);
     }
 
-    if let Err(e) = age_file_details(
-        &mut connection,
-        &mut fds,
-        &d,
-        settings.max_stale_days.unwrap_or(3),
-        settings.max_propagation_days.unwrap_or(2),
-    ) {
-        println!("File Detail aging failed {}", e);
-        process::exit(1);
+    // Categories no worker was able to scan
+    for (i, (category, _)) in categories.iter().enumerate() {
+        if !results.iter().any(|(r, _)| *r == i) {
+            let result = Err(String::from("Category has not been scanned"));
+            results.push((
+                i,
+                CategoryResult {
+                    name: category.name.clone(),
+                    skipped: false,
+                    dry_run_changes: Vec::new(),
+                    report: report::take(&category.name, &result),
+                    result,
+                },
+            ));
+        }
+    }
+    let mut results: Vec<(usize, CategoryResult)> = results
+        .into_iter()
+        .map(|(i, r)| (indices[i], r))
+        .chain(skipped)
+        .collect();
+    results.sort_by_key(|(i, _)| *i);
+    let results: Vec<CategoryResult> = results.into_iter().map(|(_, r)| r).collect();
+
+    let dry_run_changes: Vec<(String, Vec<dry_run::Change>)> = results
+        .iter()
+        .map(|r| (r.name.clone(), r.dry_run_changes.clone()))
+        .collect();
+
+    match params.dry_run_format.as_deref() {
+        Some("json") => dry_run::print_json(&dry_run_changes),
+        Some(_) => dry_run::print_table(&dry_run_changes),
+        _ => (),
+    }
+
+    if results.len() > 1 {
+        summary(&results).printstd();
     }
 
-    if params.delete_directories {
-        if let Err(e) = cleanup_database(&mut connection, &cds, &d, topdir) {
-            println!("Database cleanup failed {}", e);
+    let reports: Vec<report::CategoryReport> = results.iter().map(|r| r.report.clone()).collect();
+
+    if let Some(path) = &params.metrics_textfile {
+        // A dry-run must not look like a successful scan to the monitoring
+        if dry_run::is_enabled() {
+            warn!("Not writing metrics to '{}' in dry-run mode", path);
+        } else if let Err(e) = metrics::write(
+            Path::new(path),
+            // Skipped categories keep the metrics of their last scan
+            &results
+                .iter()
+                .filter(|r| !r.skipped)
+                .map(|r| r.report.clone())
+                .collect::<Vec<_>>(),
+        ) {
+            error!("Writing metrics to '{}' failed: {}", path, e);
             process::exit(1);
         }
     }
+
+    if let Some(path) = &params.report_json {
+        if let Err(e) = report::write(
+            Path::new(path),
+            &report::Report {
+                started,
+                finished: chrono::offset::Local::now().timestamp(),
+                dry_run: dry_run::is_enabled(),
+                categories: &reports,
+            },
+        ) {
+            error!("Writing report to '{}' failed: {}", path, e);
+            process::exit(1);
+        }
+    }
+
+    process::exit(exit_code(&results));
 }
 
 #[cfg(test)]
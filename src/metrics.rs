@@ -0,0 +1,202 @@
+// SPDX-License-Identifier: MIT
+
+use crate::report::CategoryReport;
+
+use regex::Regex;
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::path::Path;
+
+const PREFIX: &str = "mirrormanager_scan";
+
+/// Name, type and help text of each metric written.
+const METRICS: &[(&str, &str, &str)] = &[
+    (
+        "last_success_timestamp_seconds",
+        "gauge",
+        "Unix timestamp of the last successful scan of the category",
+    ),
+    (
+        "last_run_timestamp_seconds",
+        "gauge",
+        "Unix timestamp of the last scan of the category",
+    ),
+    (
+        "success",
+        "gauge",
+        "1 if the last scan of the category succeeded, 0 otherwise",
+    ),
+    (
+        "phase_duration_seconds",
+        "gauge",
+        "Duration of each phase of the last scan of the category",
+    ),
+    (
+        "directories",
+        "gauge",
+        "Directories scanned, added, updated and deleted by the last scan",
+    ),
+    (
+        "repositories_created",
+        "gauge",
+        "Repositories created by the last scan of the category",
+    ),
+    (
+        "file_details",
+        "gauge",
+        "file_detail rows inserted, aged and pruned by the last scan of the category",
+    ),
+    (
+        "checksum_failures",
+        "gauge",
+        "Files for which the last scan could not retrieve checksums",
+    ),
+];
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn unescape(value: &str) -> String {
+    value
+        .replace("\\n", "\n")
+        .replace("\\\"", "\"")
+        .replace("\\\\", "\\")
+}
+
+/// The metrics of the categories in a previously written textfile.
+///
+/// Returns the samples of each metric by category.
+fn parse_previous(previous: &str) -> HashMap<String, Vec<(String, String)>> {
+    let sample = Regex::new(r#"^(\w+)\{category="((?:[^"\\]|\\.)*)"[^}]*\}"#).unwrap();
+    let mut samples: HashMap<String, Vec<(String, String)>> = HashMap::new();
+
+    for line in previous.lines() {
+        if let Some(m) = sample.captures(line) {
+            samples
+                .entry(unescape(&m[2]))
+                .or_default()
+                .push((m[1].to_string(), line.to_string()));
+        }
+    }
+
+    samples
+}
+
+/// Create the content of a node-exporter textfile.
+///
+/// * `previous` - the content of the textfile written by an earlier run.
+///   The metrics of categories not scanned in this run are kept. The
+///   timestamp of the last successful scan of a failed category is kept.
+/// * `now` - Unix timestamp written as the time of this run
+pub fn textfile(reports: &[CategoryReport], previous: &str, now: i64) -> String {
+    let mut samples = parse_previous(previous);
+    let mut metrics: BTreeMap<String, Vec<String>> = BTreeMap::new();
+
+    for r in reports {
+        let label = format!("category=\"{}\"", escape(&r.category));
+        let old = samples.remove(&r.category).unwrap_or_default();
+        let success = r.result == "OK";
+        let last_success = match success {
+            true => Some(now.to_string()),
+            false => old
+                .iter()
+                .find(|(n, _)| *n == format!("{}_last_success_timestamp_seconds", PREFIX))
+                .and_then(|(_, line)| line.rsplit(' ').next())
+                .map(str::to_string),
+        };
+
+        let mut add = |name: &str, labels: String, value: String| {
+            metrics
+                .entry(format!("{}_{}", PREFIX, name))
+                .or_default()
+                .push(format!("{}_{}{{{}}} {}", PREFIX, name, labels, value));
+        };
+
+        if let Some(ts) = last_success {
+            add("last_success_timestamp_seconds", label.clone(), ts);
+        }
+        add("last_run_timestamp_seconds", label.clone(), now.to_string());
+        add(
+            "success",
+            label.clone(),
+            String::from(if success { "1" } else { "0" }),
+        );
+        for (phase, duration) in &r.phase_durations {
+            add(
+                "phase_duration_seconds",
+                format!("{},phase=\"{}\"", label, escape(phase)),
+                duration.to_string(),
+            );
+        }
+        for (state, count) in [
+            ("scanned", r.scanned_directories),
+            ("added", r.counts.new_directories),
+            ("updated", r.counts.changed_directories),
+            ("deleted", r.counts.removed_directories),
+        ] {
+            add(
+                "directories",
+                format!("{},state=\"{}\"", label, state),
+                count.to_string(),
+            );
+        }
+        add(
+            "repositories_created",
+            label.clone(),
+            r.counts.created_repositories.to_string(),
+        );
+        for (state, count) in [
+            ("inserted", r.counts.inserted_file_details),
+            ("aged", r.counts.aged_file_details),
+            ("pruned", r.counts.pruned_file_details),
+        ] {
+            add(
+                "file_details",
+                format!("{},state=\"{}\"", label, state),
+                count.to_string(),
+            );
+        }
+        add(
+            "checksum_failures",
+            label.clone(),
+            r.checksum_failures.to_string(),
+        );
+    }
+
+    // Categories not scanned in this run
+    for (name, line) in samples.into_values().flatten() {
+        metrics.entry(name).or_default().push(line);
+    }
+
+    let mut content = String::new();
+    for (name, r#type, help) in METRICS {
+        let name = format!("{}_{}", PREFIX, name);
+        let mut lines = match metrics.remove(&name) {
+            Some(l) => l,
+            _ => continue,
+        };
+        lines.sort();
+        content.push_str(&format!("# HELP {} {}\n", name, help));
+        content.push_str(&format!("# TYPE {} {}\n", name, r#type));
+        for l in lines {
+            content.push_str(&l);
+            content.push('\n');
+        }
+    }
+
+    content
+}
+
+/// Write the metrics of this run to the node-exporter textfile `path`.
+pub fn write(path: &Path, reports: &[CategoryReport]) -> Result<(), Box<dyn Error>> {
+    let previous = std::fs::read_to_string(path).unwrap_or_default();
+    let now = chrono::offset::Local::now().timestamp();
+
+    crate::filelist::write_if_changed(path, &textfile(reports, &previous, now))?;
+
+    Ok(())
+}
@@ -0,0 +1,316 @@
+// SPDX-License-Identifier: MIT
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashSet};
+use std::error::Error;
+use std::path::Path;
+
+thread_local! {
+    /// The report of the category currently scanned on this thread.
+    static CURRENT: RefCell<CategoryReport> = RefCell::new(CategoryReport::default());
+}
+
+/// A path which has not been scanned because of an exclude.
+#[derive(Debug, Clone, Serialize)]
+pub struct Exclusion {
+    pub path: String,
+    /// The exclude regex which matched
+    pub pattern: String,
+}
+
+/// A repodata directory for which no repository has been created.
+#[derive(Debug, Clone, Serialize)]
+pub struct SkippedRepodata {
+    pub directory: String,
+    pub reason: String,
+}
+
+/// A checksum file with a valid OpenPGP signature.
+#[derive(Debug, Clone, Serialize)]
+pub struct Signature {
+    pub file: String,
+    /// Fingerprint of the key which made the signature
+    pub fingerprint: String,
+}
+
+/// A file whose content does not match its checksum file.
+#[derive(Debug, Clone, Serialize)]
+pub struct ChecksumMismatch {
+    pub file: String,
+    pub reason: String,
+}
+
+/// The number of entries in each list of a `CategoryReport`.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct Counts {
+    pub new_directories: usize,
+    pub changed_directories: usize,
+    pub removed_directories: usize,
+    pub created_repositories: usize,
+    pub disabled_repositories: usize,
+    pub reenabled_repositories: usize,
+    pub created_versions: usize,
+    pub inserted_file_details: usize,
+    pub aged_file_details: usize,
+    pub pruned_file_details: usize,
+    pub exclusions: usize,
+    pub skipped_repodata: usize,
+    pub signatures: usize,
+    pub checksum_mismatches: usize,
+    pub errors: usize,
+}
+
+/// Everything a scan of one category changed or ran into.
+///
+/// If the scan of the category failed the database changes
+/// listed here have been rolled back. Unless only the removal of
+/// directories has been skipped because of the delete limits or
+/// `--phase-savepoints` kept the phases before the failed one.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct CategoryReport {
+    pub category: String,
+    /// OK, FAILED or SKIPPED
+    pub result: String,
+    pub counts: Counts,
+    /// Number of directories found by the scan
+    pub scanned_directories: usize,
+    /// Number of files for which no checksums could be retrieved
+    pub checksum_failures: usize,
+    /// Duration of each phase of the scan in seconds
+    pub phase_durations: BTreeMap<String, f64>,
+    pub new_directories: Vec<String>,
+    pub changed_directories: Vec<String>,
+    pub removed_directories: Vec<String>,
+    pub created_repositories: Vec<String>,
+    pub disabled_repositories: Vec<String>,
+    pub reenabled_repositories: Vec<String>,
+    pub created_versions: Vec<String>,
+    pub inserted_file_details: Vec<String>,
+    pub aged_file_details: Vec<i32>,
+    /// `file_detail` rows of repodata files no longer referenced by `repomd.xml`
+    pub pruned_file_details: Vec<i32>,
+    pub exclusions: Vec<Exclusion>,
+    pub skipped_repodata: Vec<SkippedRepodata>,
+    pub signatures: Vec<Signature>,
+    pub checksum_mismatches: Vec<ChecksumMismatch>,
+    pub errors: Vec<String>,
+    /// The paths in `exclusions` to find paths below them
+    #[serde(skip)]
+    excluded_paths: HashSet<String>,
+}
+
+/// The report of a complete run written by `--report-json`.
+#[derive(Debug, Serialize)]
+pub struct Report<'a> {
+    /// Unix timestamps of the start and the end of the run
+    pub started: i64,
+    pub finished: i64,
+    pub dry_run: bool,
+    pub categories: &'a [CategoryReport],
+}
+
+fn with_current<T>(f: impl FnOnce(&mut CategoryReport) -> T) -> T {
+    CURRENT.with(|r| f(&mut r.borrow_mut()))
+}
+
+pub fn new_directory(name: &str) {
+    with_current(|r| r.new_directories.push(name.to_string()));
+}
+
+pub fn changed_directory(name: &str) {
+    with_current(|r| r.changed_directories.push(name.to_string()));
+}
+
+pub fn removed_directory(name: &str) {
+    with_current(|r| r.removed_directories.push(name.to_string()));
+}
+
+pub fn created_repository(prefix: &str) {
+    with_current(|r| r.created_repositories.push(prefix.to_string()));
+}
+
+/// Record a repository detached from its deleted directory.
+pub fn disabled_repository(prefix: &str) {
+    with_current(|r| r.disabled_repositories.push(prefix.to_string()));
+}
+
+/// Record a detached repository attached to a new directory.
+pub fn reenabled_repository(prefix: &str) {
+    with_current(|r| r.reenabled_repositories.push(prefix.to_string()));
+}
+
+pub fn created_version(name: &str) {
+    with_current(|r| r.created_versions.push(name.to_string()));
+}
+
+/// Record a new `file_detail` row for `filename` in `directory`.
+pub fn inserted_file_detail(directory: &str, filename: &str) {
+    with_current(|r| {
+        r.inserted_file_details
+            .push(format!("{}/{}", directory, filename))
+    });
+}
+
+/// Record the ID of a deleted `file_detail` row.
+pub fn aged_file_detail(id: i32) {
+    with_current(|r| r.aged_file_details.push(id));
+}
+
+/// Record the ID of a deleted `file_detail` row of a repodata
+/// file no longer referenced by `repomd.xml`.
+pub fn pruned_file_detail(id: i32) {
+    with_current(|r| r.pruned_file_details.push(id));
+}
+
+/// Record a `path` excluded because of `pattern`.
+///
+/// Everything below an excluded directory is excluded as well. Only
+/// the directory itself is recorded. Returns `false` if `path` or one
+/// of its parents has already been recorded.
+pub fn excluded(path: &str, pattern: &str) -> bool {
+    let path = path.trim_end_matches('/');
+    with_current(|r| {
+        let mut parents = std::iter::successors(Some(path), |p| p.rfind('/').map(|i| &p[..i]));
+        if parents.any(|p| r.excluded_paths.contains(p)) {
+            return false;
+        }
+        r.excluded_paths.insert(path.to_string());
+        r.exclusions.push(Exclusion {
+            path: path.to_string(),
+            pattern: pattern.to_string(),
+        });
+        true
+    })
+}
+
+pub fn skipped_repodata(directory: &str, reason: String) {
+    with_current(|r| {
+        r.skipped_repodata.push(SkippedRepodata {
+            directory: directory.to_string(),
+            reason,
+        })
+    });
+}
+
+/// Record the key which signed the checksum `file`.
+pub fn signature(file: &str, fingerprint: &str) {
+    with_current(|r| {
+        r.signatures.push(Signature {
+            file: file.to_string(),
+            fingerprint: fingerprint.to_string(),
+        })
+    });
+}
+
+/// Record a file which has not been recorded because
+/// its content does not match the checksum file.
+pub fn checksum_mismatch(file: &str, reason: String) {
+    with_current(|r| {
+        r.checksum_mismatches.push(ChecksumMismatch {
+            file: file.to_string(),
+            reason,
+        })
+    });
+}
+
+/// Record a file for which retrieving the checksums failed.
+pub fn checksum_failure(message: String) {
+    with_current(|r| {
+        r.checksum_failures += 1;
+        r.errors.push(message)
+    });
+}
+
+pub fn scanned_directories(count: usize) {
+    with_current(|r| r.scanned_directories = count);
+}
+
+pub fn phase_duration(phase: &str, duration: std::time::Duration) {
+    with_current(|r| {
+        r.phase_durations
+            .insert(phase.to_string(), duration.as_secs_f64());
+    });
+}
+
+/// The report of a category which has not been scanned.
+pub fn skipped(name: &str, reason: &str) -> CategoryReport {
+    CategoryReport {
+        category: name.to_string(),
+        result: String::from("SKIPPED"),
+        errors: vec![reason.to_string()],
+        counts: Counts {
+            errors: 1,
+            ..Default::default()
+        },
+        ..Default::default()
+    }
+}
+
+/// Returns the report of the category `name` and resets it.
+pub fn take(name: &str, result: &Result<(), String>) -> CategoryReport {
+    let mut report = CURRENT.with(|r| r.replace(CategoryReport::default()));
+
+    report.category = name.to_string();
+    match result {
+        Ok(_) => report.result = String::from("OK"),
+        Err(e) => {
+            report.result = String::from("FAILED");
+            report.errors.push(e.to_string());
+        }
+    }
+    report.counts = Counts {
+        new_directories: report.new_directories.len(),
+        changed_directories: report.changed_directories.len(),
+        removed_directories: report.removed_directories.len(),
+        created_repositories: report.created_repositories.len(),
+        disabled_repositories: report.disabled_repositories.len(),
+        reenabled_repositories: report.reenabled_repositories.len(),
+        created_versions: report.created_versions.len(),
+        inserted_file_details: report.inserted_file_details.len(),
+        aged_file_details: report.aged_file_details.len(),
+        pruned_file_details: report.pruned_file_details.len(),
+        exclusions: report.exclusions.len(),
+        skipped_repodata: report.skipped_repodata.len(),
+        signatures: report.signatures.len(),
+        checksum_mismatches: report.checksum_mismatches.len(),
+        errors: report.errors.len(),
+    };
+
+    report
+}
+
+/// Add the records of `other` to the report of this thread.
+///
+/// `other` is the report taken on a helper thread which did
+/// work for the category scanned on this thread.
+pub fn merge(other: CategoryReport) {
+    with_current(|r| {
+        r.new_directories.extend(other.new_directories);
+        r.changed_directories.extend(other.changed_directories);
+        r.removed_directories.extend(other.removed_directories);
+        r.created_repositories.extend(other.created_repositories);
+        r.disabled_repositories.extend(other.disabled_repositories);
+        r.reenabled_repositories
+            .extend(other.reenabled_repositories);
+        r.created_versions.extend(other.created_versions);
+        r.inserted_file_details.extend(other.inserted_file_details);
+        r.aged_file_details.extend(other.aged_file_details);
+        r.pruned_file_details.extend(other.pruned_file_details);
+        r.exclusions.extend(other.exclusions);
+        r.excluded_paths.extend(other.excluded_paths);
+        r.skipped_repodata.extend(other.skipped_repodata);
+        r.signatures.extend(other.signatures);
+        r.checksum_mismatches.extend(other.checksum_mismatches);
+        r.checksum_failures += other.checksum_failures;
+        r.errors.extend(other.errors);
+    });
+}
+
+/// Write the report of the run to `path`.
+pub fn write(path: &Path, report: &Report) -> Result<(), Box<dyn Error>> {
+    let mut json = serde_json::to_string_pretty(report)?;
+    json.push('\n');
+    crate::filelist::write_if_changed(path, &json)?;
+
+    Ok(())
+}
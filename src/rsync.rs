@@ -0,0 +1,167 @@
+// SPDX-License-Identifier: MIT
+
+use crate::FileInfo;
+
+use log::{debug, warn};
+use std::error::Error;
+use std::io::{BufRead, BufReader, Read};
+use std::process::{Command, ExitStatus, Stdio};
+
+/// The meaning of the exit codes of rsync as documented in rsync(1).
+fn exit_code_meaning(code: i32) -> &'static str {
+    match code {
+        1 => "syntax or usage error",
+        2 => "protocol incompatibility",
+        3 => "errors selecting input/output files, dirs",
+        4 => "requested action not supported",
+        5 => "error starting client-server protocol",
+        6 => "daemon unable to append to log-file",
+        10 => "error in socket I/O",
+        11 => "error in file I/O",
+        12 => "error in rsync protocol data stream",
+        13 => "errors with program diagnostics",
+        14 => "error in IPC code",
+        20 => "received SIGUSR1 or SIGINT",
+        21 => "some error returned by waitpid()",
+        22 => "error allocating core memory buffers",
+        23 => "partial transfer due to error",
+        24 => "partial transfer due to vanished source files",
+        25 => "the --max-delete limit stopped deletions",
+        30 => "timeout in data send/receive",
+        35 => "timeout waiting for daemon connection",
+        _ => "unknown error",
+    }
+}
+
+/// Describe the exit `status` of rsync for error messages.
+pub fn describe_exit(status: &ExitStatus) -> String {
+    match status.code() {
+        Some(code) => format!("exit code {} ({})", code, exit_code_meaning(code)),
+        None => String::from("termination by a signal"),
+    }
+}
+
+/// Check if `mode` is the file mode column of a listing (`drwxr-xr-x`).
+fn is_mode(mode: &str) -> bool {
+    mode.len() == 10
+        && mode.starts_with(['-', 'd', 'l', 'c', 'b', 'p', 's'])
+        && mode[1..].chars().all(|c| "rwxsStT-".contains(c))
+}
+
+/// Parse one line of an `rsync -r --no-human-readable` listing:
+///
+/// `drwxr-xr-x           4096 2021/03/19 12:34:56 some/name`
+///
+/// Returns `None` for lines which are not part of the listing (like
+/// the MOTD) and for entries which are neither a regular file nor a
+/// directory. Fails for a listing entry which cannot be parsed.
+pub fn parse_line(line: &str) -> Result<Option<FileInfo>, Box<dyn Error>> {
+    let mut fields = line.splitn(2, ' ');
+    let mode = fields.next().unwrap_or_default();
+    if !is_mode(mode) {
+        return Ok(None);
+    }
+
+    let rest = fields.next().unwrap_or_default().trim_start();
+    let mut fields = rest.splitn(4, ' ');
+    let (size, date, time, name) =
+        match (fields.next(), fields.next(), fields.next(), fields.next()) {
+            (Some(s), Some(d), Some(t), Some(n)) if !n.is_empty() => (s, d, t, n),
+            _ => return Err(format!("Incomplete rsync listing entry '{}'", line).into()),
+        };
+    let size: i64 = size
+        .parse()
+        .map_err(|e| format!("Invalid size in rsync listing entry '{}': {}", line, e))?;
+    let timestamp = crate::ctime_from_rsync(date.to_string(), time.to_string());
+    if timestamp == -1 {
+        return Err(format!("Invalid date in rsync listing entry '{}'", line).into());
+    }
+
+    if !mode.starts_with(['-', 'd']) {
+        debug!("Ignoring {}", name);
+        return Ok(None);
+    }
+
+    Ok(Some(FileInfo {
+        is_directory: mode.starts_with('d'),
+        // Only used for directories: readable and searchable by others
+        is_readable: mode.starts_with('d') && &mode[7..8] == "r" && matches!(&mode[9..], "x" | "t"),
+        size,
+        timestamp,
+        name: Some(name.to_string()),
+    }))
+}
+
+/// Run `rsync` with `args` and pass each entry of the listing to `f`.
+///
+/// The listing is processed while rsync is still running. Fails if
+/// rsync does not exit successfully as the listing is incomplete
+/// then. Only files vanishing during the listing (exit code 24)
+/// are tolerated.
+pub fn list(args: &[String], mut f: impl FnMut(FileInfo)) -> Result<(), Box<dyn Error>> {
+    debug!("Running rsync {}", args.join(" "));
+
+    let mut child = Command::new("rsync")
+        .args(args)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("Running rsync failed: {}", e))?;
+
+    // Read stderr on its own thread to not block rsync if the pipe is full
+    let mut stderr = child.stderr.take().unwrap();
+    let stderr = std::thread::spawn(move || {
+        let mut output = String::new();
+        let _ = stderr.read_to_string(&mut output);
+        output
+    });
+
+    let mut result: Result<(), Box<dyn Error>> = Ok(());
+    let mut stdout = BufReader::new(child.stdout.take().unwrap());
+    let mut line: Vec<u8> = Vec::new();
+    loop {
+        line.clear();
+        match stdout.read_until(b'\n', &mut line) {
+            Ok(0) => break,
+            Ok(_) => (),
+            Err(e) => {
+                result = Err(format!("Reading rsync output failed: {}", e).into());
+                break;
+            }
+        }
+        let parsed = std::str::from_utf8(&line)
+            .map_err(|e| format!("Invalid rsync output: {}", e).into())
+            .and_then(|l| parse_line(l.trim_end_matches(['\n', '\r'])));
+        match parsed {
+            Ok(Some(entry)) => f(entry),
+            Ok(None) => (),
+            Err(e) => {
+                result = Err(e);
+                break;
+            }
+        }
+    }
+
+    if result.is_err() {
+        // The listing is useless anyway
+        let _ = child.kill();
+    }
+    drop(stdout);
+    let status = child.wait()?;
+    let stderr = stderr.join().unwrap_or_default();
+    result?;
+
+    match status.code() {
+        Some(0) => Ok(()),
+        Some(24) => {
+            warn!("Files vanished while listing with rsync: {}", stderr.trim());
+            Ok(())
+        }
+        _ => Err(format!(
+            "rsync failed with {}. The listing is incomplete: {}",
+            describe_exit(&status),
+            stderr.trim()
+        )
+        .into()),
+    }
+}
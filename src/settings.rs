@@ -46,21 +46,112 @@ pub struct RepositoryMapping {
     pub version_prefix: Option<String>,
 }
 
+/// Settings of the HTTP(S) client used to download files from
+/// `checksum_base`. Every field not set for a category is taken
+/// from the global `[http]` section.
+///
+/// # Examples
+///
+/// ```
+/// [http]
+/// connect_timeout=10
+/// read_timeout=60
+/// retries=3
+/// user_agent="scan-primary-mirror (mirror.example.com)"
+/// ```
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct Http {
+    /// connect_timeout: seconds to wait for a connection
+    pub connect_timeout: Option<u64>,
+    /// read_timeout: seconds to wait for the response and for each
+    /// further block of data from the server. The whole download can
+    /// take longer. Defaults to 30.
+    pub read_timeout: Option<u64>,
+    /// retries: number of retries after a connection error, a timeout
+    /// or a 5xx response. Defaults to 0.
+    pub retries: Option<u32>,
+    /// retry_backoff: seconds to wait before the first retry. The wait
+    /// is doubled for each further retry. Defaults to 1.
+    pub retry_backoff: Option<u64>,
+    /// ca_file: PEM file with additional trusted CA certificates
+    pub ca_file: Option<String>,
+    /// client_cert: PEM file with the client certificate and its private key
+    pub client_cert: Option<String>,
+    /// proxy: URL of the proxy used for all requests
+    pub proxy: Option<String>,
+    /// username: user name for basic authentication
+    pub username: Option<String>,
+    /// password_file: file containing the password for basic authentication
+    pub password_file: Option<String>,
+    /// token_file: file containing a bearer token
+    pub token_file: Option<String>,
+    /// user_agent: User-Agent header sent with each request
+    pub user_agent: Option<String>,
+}
+
+impl Http {
+    /// Combine the `category` settings with the `global` ones.
+    /// Each field set for the category overrides the global value.
+    pub fn merge(global: Option<&Http>, category: Option<&Http>) -> Http {
+        let global = global.cloned().unwrap_or_default();
+        let category = match category {
+            Some(c) => c.clone(),
+            None => return global,
+        };
+
+        Http {
+            connect_timeout: category.connect_timeout.or(global.connect_timeout),
+            read_timeout: category.read_timeout.or(global.read_timeout),
+            retries: category.retries.or(global.retries),
+            retry_backoff: category.retry_backoff.or(global.retry_backoff),
+            ca_file: category.ca_file.or(global.ca_file),
+            client_cert: category.client_cert.or(global.client_cert),
+            proxy: category.proxy.or(global.proxy),
+            username: category.username.or(global.username),
+            password_file: category.password_file.or(global.password_file),
+            token_file: category.token_file.or(global.token_file),
+            user_agent: category.user_agent.or(global.user_agent),
+        }
+    }
+}
+
 #[derive(Debug, Deserialize, Default, Clone)]
 pub struct Category {
     /// name: category name like in the MM database
     pub name: String,
-    /// type: rsync or directory
+    /// type: rsync, http or directory
     pub r#type: String,
-    /// url: rsync url or path
+    /// url: rsync url, http(s) url of a directory index or path
     pub url: String,
     /// options: additional rsync parameters
     pub options: Option<String>,
-    /// checksum_base: in case of rsync this needs to be an http(s)
+    /// checksum_base: in case of rsync or http this needs to be an http(s)
     /// url used to download repomd.xml for hashsum generation
     pub checksum_base: Option<String>,
     /// excludes: comma separated list of regex for directories to exclude
     pub excludes: Option<Vec<String>>,
+    /// fullfiletimelist: name of the remote fullfiletimelist used by rsync
+    /// and http categories instead of a full listing. If not set the
+    /// first file matching `fullfiletimelist-*` is used.
+    /// For directory categories this is the file written
+    /// with `--write-filelists`.
+    pub fullfiletimelist: Option<String>,
+    /// imagelist: name of the imagelist written with `--write-filelists`
+    pub imagelist: Option<String>,
+    /// keyring: path of a file with the OpenPGP public keys (armored or
+    /// binary) of this category. If set the checksums of a checksum
+    /// file are only used if the file is clear-signed by one of the keys.
+    pub keyring: Option<String>,
+    /// checksum_files: file name patterns of checksum files of this
+    /// category. Overrides the global `checksum_files`.
+    pub checksum_files: Option<Vec<String>>,
+    /// verify_checksums: only for directory categories. Hash the local
+    /// files listed in checksum files and skip the entries which do not
+    /// match the content of the file.
+    pub verify_checksums: Option<bool>,
+    /// http: settings of the HTTP(S) client downloading from
+    /// `checksum_base`. Overrides the global `[http]` section.
+    pub http: Option<Http>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -69,6 +160,9 @@ pub struct Settings {
     pub common_rsync_options: Option<String>,
     pub max_propagation_days: Option<i64>,
     pub max_stale_days: Option<i64>,
+    /// A remote fullfiletimelist older than this is ignored and
+    /// a full listing of the category is done instead.
+    pub max_fullfiletimelist_hours: Option<i64>,
     pub database: Database,
     /// Comma separated list of regex for directories to exclude.
     /// This will be combined with the category specific excludes.
@@ -85,6 +179,50 @@ pub struct Settings {
     /// Comma separated list of path elements which should not be
     /// shown in the mirror list matrix
     pub do_not_display_paths: Option<Vec<String>>,
+    /// Create file_detail entries for all files referenced by
+    /// repomd.xml (primary, filelists, updateinfo, ...) and not
+    /// only for repomd.xml itself.
+    pub repodata_file_details: Option<bool>,
+    /// Glob patterns of file names which are parsed for checksums
+    /// in BSD (`SHA256 (name) = hash`) or GNU (`hash  name`) format.
+    /// Defaults to `*-CHECKSUM`.
+    pub checksum_files: Option<Vec<String>>,
+    /// Files larger than this (in bytes) are not hashed by
+    /// `verify_checksums`. Their checksums are used unverified.
+    pub verify_checksums_max_size: Option<u64>,
+    /// Number of files hashed in parallel by `verify_checksums`.
+    /// Defaults to the number of CPUs.
+    pub verify_checksums_jobs: Option<usize>,
+    /// File to keep the checksums of verified files between runs.
+    /// A file is only hashed again if its size or mtime changed.
+    pub verify_checksums_cache: Option<String>,
+    /// File to keep the ETag, Last-Modified and the computed details of
+    /// files downloaded from `checksum_base` between runs. Unchanged
+    /// files are neither downloaded nor hashed again.
+    pub details_cache: Option<String>,
+    /// Digests (sha384, sha3-256, blake2b) computed for new `file_detail`
+    /// entries in addition to md5, sha1, sha256 and sha512. They are
+    /// stored in the table `file_detail_digest` if it exists.
+    pub extra_digests: Option<Vec<String>>,
+    /// Default settings of the HTTP(S) client downloading
+    /// files from `checksum_base`
+    pub http: Option<Http>,
+    /// Number of checksum files and `repomd.xml` files of a
+    /// category retrieved in parallel. Defaults to 4.
+    pub fetch_jobs: Option<usize>,
+    /// `--delete-directories` refuses to delete more directories
+    /// of a category than this unless `--force` is given.
+    /// Defaults to 1000.
+    pub delete_directories_max_count: Option<usize>,
+    /// `--delete-directories` refuses to delete more than this percentage
+    /// of the directories of a category unless `--force` is given.
+    /// Defaults to 10.
+    pub delete_directories_max_percent: Option<f64>,
+    /// `--delete-directories` disables the repositories of deleted
+    /// directories and detaches them instead of deleting them. They
+    /// are attached again once a directory with the same prefix and
+    /// architecture shows up (like after moving to an archive).
+    pub soft_delete_repositories: Option<bool>,
     /// At least one category is required
     pub category: Option<Vec<Category>>,
     /// Used to create repository prefixes
@@ -0,0 +1,87 @@
+// SPDX-License-Identifier: MIT
+
+use pgp::composed::{CleartextSignedMessage, Deserializable, SignedPublicKey};
+use pgp::types::KeyDetails;
+use std::error::Error;
+
+/// The OpenPGP public keys trusted to sign checksum files.
+pub struct Keyring {
+    keys: Vec<SignedPublicKey>,
+}
+
+impl Keyring {
+    /// Read all public keys from `path`.
+    ///
+    /// The keyring can be ASCII-armored or binary (like the output
+    /// of `gpg --export`) and can contain multiple keys. Keys with
+    /// invalid self-signatures are rejected.
+    pub fn load(path: &str) -> Result<Keyring, Box<dyn Error>> {
+        let file = std::fs::File::open(path)
+            .map_err(|e| format!("Opening keyring {} failed: {}", path, e))?;
+        let (parsed, _) = SignedPublicKey::from_reader_many(file)
+            .map_err(|e| format!("Reading keyring {} failed: {}", path, e))?;
+
+        let mut keys = Vec::new();
+        for key in parsed {
+            let key = key.map_err(|e| format!("Reading keyring {} failed: {}", path, e))?;
+            key.verify_bindings().map_err(|e| {
+                format!(
+                    "Key {:X} in keyring {} is invalid: {}",
+                    key.fingerprint(),
+                    path,
+                    e
+                )
+            })?;
+            keys.push(key);
+        }
+
+        if keys.is_empty() {
+            return Err(format!("Keyring {} does not contain any keys", path).into());
+        }
+
+        Ok(Keyring { keys })
+    }
+
+    /// Check if `fingerprint` is the fingerprint of one of the keys
+    /// or subkeys of this keyring.
+    pub fn contains(&self, fingerprint: &str) -> bool {
+        self.keys.iter().any(|k| {
+            format!("{:X}", k.fingerprint()) == fingerprint
+                || k.public_subkeys
+                    .iter()
+                    .any(|s| format!("{:X}", s.fingerprint()) == fingerprint)
+        })
+    }
+}
+
+/// Verify the OpenPGP signature of the clear-signed `text`.
+///
+/// Returns the signed text and the fingerprint of the key which made
+/// the signature. The primary key and all subkeys of each key in
+/// `keyring` are tried. Only the returned text is covered by the
+/// signature; everything outside of the signed block is dropped.
+pub fn verify_cleartext(text: &str, keyring: &Keyring) -> Result<(String, String), Box<dyn Error>> {
+    let (message, _) = CleartextSignedMessage::from_string(text)
+        .map_err(|e| format!("Not a clear-signed message: {}", e))?;
+
+    for key in &keyring.keys {
+        let fingerprint = if message.verify(&key.primary_key).is_ok() {
+            key.fingerprint()
+        } else if let Some(subkey) = key
+            .public_subkeys
+            .iter()
+            .find(|s| message.verify(*s).is_ok())
+        {
+            subkey.fingerprint()
+        } else {
+            continue;
+        };
+
+        return Ok((
+            message.signed_text().replace("\r\n", "\n"),
+            format!("{:X}", fingerprint),
+        ));
+    }
+
+    Err("No valid signature by a key of the keyring".into())
+}
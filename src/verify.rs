@@ -0,0 +1,206 @@
+// SPDX-License-Identifier: MIT
+
+use crate::digests::{self, Digests};
+
+use log::{debug, warn};
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+/// The digests of a local file as long as its size and mtime are unchanged.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CacheEntry {
+    size: u64,
+    /// Modification time in nanoseconds since the epoch
+    mtime_ns: u64,
+    digests: Digests,
+}
+
+/// Digests of local files computed during this or earlier runs.
+///
+/// Shared by all categories scanned in parallel. It is only
+/// written to disk at the end of the run.
+#[derive(Debug, Default)]
+pub struct Cache {
+    path: Option<PathBuf>,
+    entries: Mutex<HashMap<String, CacheEntry>>,
+    changed: AtomicBool,
+}
+
+impl Cache {
+    /// Read the cache written by an earlier run from `path`.
+    ///
+    /// A missing or unreadable cache file results in an empty cache.
+    /// Without a `path` the cache only lives as long as this run.
+    pub fn load(path: Option<&str>) -> Cache {
+        let path = match path {
+            Some(p) => PathBuf::from(p),
+            None => return Cache::default(),
+        };
+
+        let entries = match std::fs::read(&path) {
+            Ok(data) => serde_json::from_slice(&data).unwrap_or_else(|e| {
+                warn!("Ignoring checksum cache {}: {}", path.display(), e);
+                HashMap::new()
+            }),
+            Err(_) => HashMap::new(),
+        };
+
+        Cache {
+            path: Some(path),
+            entries: Mutex::new(entries),
+            changed: AtomicBool::new(false),
+        }
+    }
+
+    /// Write the cache if it has changed.
+    ///
+    /// Entries of files which no longer exist are dropped.
+    pub fn save(&self) -> Result<(), Box<dyn Error>> {
+        let path = match &self.path {
+            Some(p) => p,
+            None => return Ok(()),
+        };
+        let mut entries = self.entries.lock().unwrap();
+        let count = entries.len();
+        entries.retain(|p, _| Path::new(p).exists());
+        if !self.changed.load(Ordering::SeqCst) && count == entries.len() {
+            return Ok(());
+        }
+
+        let mut json = serde_json::to_string(&*entries)?;
+        json.push('\n');
+        crate::filelist::write_if_changed(path, &json)?;
+
+        Ok(())
+    }
+
+    /// Returns the digests of the local file `path`.
+    ///
+    /// The digests are only computed if the file has not been seen
+    /// before with the same size and mtime. Returns `None` if the file
+    /// is larger than `max_size` bytes.
+    pub fn digests(&self, path: &Path, max_size: u64) -> Result<Option<Digests>, Box<dyn Error>> {
+        let metadata = std::fs::metadata(path)?;
+        let size = metadata.len();
+        if size > max_size {
+            return Ok(None);
+        }
+        let mtime_ns = metadata
+            .modified()?
+            .duration_since(std::time::UNIX_EPOCH)?
+            .as_nanos() as u64;
+
+        let key = path.to_string_lossy().to_string();
+        if let Some(e) = self.entries.lock().unwrap().get(&key) {
+            if e.size == size && e.mtime_ns == mtime_ns {
+                return Ok(Some(e.digests.clone()));
+            }
+        }
+
+        debug!("Hashing {} for checksum verification", path.display());
+        let digests = digests::from_reader(&mut std::fs::File::open(path)?, None, &[])?;
+        self.entries.lock().unwrap().insert(
+            key,
+            CacheEntry {
+                size,
+                mtime_ns,
+                digests: digests.clone(),
+            },
+        );
+        self.changed.store(true, Ordering::SeqCst);
+
+        Ok(Some(digests))
+    }
+}
+
+/// Verify checksum file entries against the content of the local files.
+pub struct Verifier<'a> {
+    pub cache: &'a Cache,
+    /// Files larger than this (in bytes) are not verified
+    pub max_size: u64,
+    /// Number of files hashed in parallel
+    pub jobs: usize,
+}
+
+/// The result of verifying one file.
+#[derive(Debug, PartialEq)]
+pub enum Verification {
+    /// All checksums listed for the file match its content
+    Match,
+    /// The file is larger than the size limit
+    TooLarge,
+    /// A description of the checksums which do not match
+    Mismatch(String),
+}
+
+/// Compare the `expected` digests with the `actual` ones.
+///
+/// Empty expected digests are not compared.
+pub fn compare(expected: &Digests, actual: &Digests) -> Verification {
+    let mut problems: Vec<String> = Vec::new();
+
+    for (name, e, a) in [
+        ("MD5", &expected.md5, &actual.md5),
+        ("SHA1", &expected.sha1, &actual.sha1),
+        ("SHA256", &expected.sha256, &actual.sha256),
+        ("SHA512", &expected.sha512, &actual.sha512),
+    ] {
+        if !e.is_empty() && e != a {
+            problems.push(format!("{} is {} instead of {}", name, a, e));
+        }
+    }
+    if expected.length > 0 && expected.length != actual.length {
+        problems.push(format!(
+            "size is {} instead of {}",
+            actual.length, expected.length
+        ));
+    }
+
+    match problems.is_empty() {
+        true => Verification::Match,
+        false => Verification::Mismatch(problems.join(", ")),
+    }
+}
+
+impl Verifier<'_> {
+    /// Verify each local file in `files` against its expected digests.
+    ///
+    /// Up to `jobs` files are hashed in parallel. Returns the result
+    /// of each file in the order of `files`.
+    pub fn verify(&self, files: &[(PathBuf, Digests)]) -> Vec<Verification> {
+        let next = AtomicUsize::new(0);
+        let results: Mutex<Vec<Option<Verification>>> =
+            Mutex::new(files.iter().map(|_| None).collect());
+
+        let worker = || loop {
+            let i = next.fetch_add(1, Ordering::SeqCst);
+            let (path, expected) = match files.get(i) {
+                Some(f) => f,
+                _ => break,
+            };
+            let result = match self.cache.digests(path, self.max_size) {
+                Ok(Some(actual)) => compare(expected, &actual),
+                Ok(None) => Verification::TooLarge,
+                Err(e) => Verification::Mismatch(format!("cannot be read: {}", e)),
+            };
+            results.lock().unwrap()[i] = Some(result);
+        };
+
+        std::thread::scope(|s| {
+            for _ in 1..self.jobs.clamp(1, files.len().max(1)) {
+                s.spawn(worker);
+            }
+            worker();
+        });
+
+        results
+            .into_inner()
+            .unwrap()
+            .into_iter()
+            .map(|r| r.unwrap())
+            .collect()
+    }
+}
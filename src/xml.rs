@@ -2,38 +2,113 @@ use serde_xml_rs::from_reader;
 use std::cmp;
 
 #[derive(Debug, Deserialize)]
-struct Timestamp {
+pub struct Timestamp {
     #[serde(rename = "#text")]
     pub value: String,
 }
+
 #[derive(Debug, Deserialize)]
-struct Data {
-    pub timestamp: Timestamp,
+pub struct Checksum {
+    /// md5, sha (SHA-1), sha1, sha256 or sha512
+    #[serde(rename = "@type")]
+    pub r#type: String,
+    #[serde(rename = "#text")]
+    pub value: String,
 }
 
 #[derive(Debug, Deserialize)]
-struct Project {
+pub struct Location {
+    /// Path of the file relative to the repository
+    #[serde(rename = "@href")]
+    pub href: String,
+}
+
+/// A file referenced by `repomd.xml` (primary, filelists, updateinfo, ...)
+///
+/// The complete schema is parsed even if not every field is used yet.
+#[derive(Debug, Deserialize)]
+#[allow(dead_code)]
+pub struct Data {
+    /// primary, filelists, other, primary_db, updateinfo, ...
+    #[serde(rename = "@type")]
+    pub r#type: Option<String>,
+    pub checksum: Option<Checksum>,
+    /// Checksum of the uncompressed file
+    #[serde(rename = "open-checksum")]
+    pub open_checksum: Option<Checksum>,
+    /// Checksum of the zchunk header
+    #[serde(rename = "header-checksum")]
+    pub header_checksum: Option<Checksum>,
+    pub location: Option<Location>,
+    pub timestamp: Option<Timestamp>,
+    pub size: Option<i64>,
+    /// Size of the uncompressed file
+    #[serde(rename = "open-size")]
+    pub open_size: Option<i64>,
+    /// Version of the sqlite databases (`*_db`)
+    pub database_version: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+#[allow(dead_code)]
+pub struct Distro {
+    #[serde(rename = "@cpeid")]
+    pub cpeid: Option<String>,
+    #[serde(rename = "#text")]
+    pub value: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[allow(dead_code)]
+pub struct Tags {
+    #[serde(default)]
+    pub content: Vec<String>,
+    #[serde(default)]
+    pub repo: Vec<String>,
+    #[serde(default)]
+    pub distro: Vec<Distro>,
+}
+
+#[derive(Debug, Deserialize)]
+#[allow(dead_code)]
+pub struct Repomd {
+    pub revision: Option<String>,
+    pub tags: Option<Tags>,
+    #[serde(default)]
     pub data: Vec<Data>,
 }
 
+impl Data {
+    /// The timestamp of the entry. Some tools write it as a float.
+    pub fn timestamp(&self) -> Option<i64> {
+        let value = &self.timestamp.as_ref()?.value;
+        match value.parse::<i64>() {
+            Ok(v1) => Some(v1),
+            Err(_) => match value.parse::<f64>() {
+                Ok(v2) => Some(v2 as i64),
+                Err(_) => None,
+            },
+        }
+    }
+}
+
+/// Parse the content of a `repomd.xml` file.
+pub fn parse_repomd(xml: &[u8]) -> Result<Repomd, serde_xml_rs::Error> {
+    from_reader(xml)
+}
+
 pub fn get_timestamp(xml: String) -> i64 {
-    let project: Project = match from_reader(xml.as_bytes()) {
-        Ok(p) => p,
-        Err(_) => return 0,
+    let repomd = match parse_repomd(xml.as_bytes()) {
+        Ok(p) if !p.data.is_empty() => p,
+        _ => return 0,
     };
 
     let mut timestamp: i64 = -1;
 
-    for d in project.data {
-        let value = match d.timestamp.value.parse::<i64>() {
-            Ok(v1) => v1,
-            Err(_) => match d.timestamp.value.parse::<f64>() {
-                Ok(v2) => v2 as i64,
-                Err(_) => continue,
-            },
-        };
-
-        timestamp = cmp::max(timestamp, value);
+    for d in repomd.data {
+        if let Some(value) = d.timestamp() {
+            timestamp = cmp::max(timestamp, value);
+        }
     }
 
     timestamp
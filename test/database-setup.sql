@@ -1,7 +1,8 @@
+DROP TABLE file_detail_digest;
 DROP TABLE file_detail;
 
 CREATE TABLE file_detail (
-    id serial NOT NULL,
+    id serial PRIMARY KEY,
     directory_id integer NOT NULL,
     filename text NOT NULL,
     "timestamp" bigint,
@@ -12,6 +13,13 @@ CREATE TABLE file_detail (
     sha512 text
 );
 
+CREATE TABLE file_detail_digest (
+    file_detail_id integer NOT NULL REFERENCES file_detail(id) ON DELETE CASCADE,
+    algorithm text NOT NULL,
+    digest text NOT NULL,
+    PRIMARY KEY (file_detail_id, algorithm)
+);
+
 DROP TABLE directory;
 CREATE TABLE directory (
     id serial NOT NULL,